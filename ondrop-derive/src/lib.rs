@@ -0,0 +1,590 @@
+//! Procedural macros for the [`ondrop`](https://docs.rs/ondrop) crate.
+//!
+//! Use them through `ondrop` with the `derive` feature enabled, rather than depending on this
+//! crate directly.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+
+/// Gives a struct a `Drop` impl that passes its fields by value to a method.
+///
+/// The method is named with `#[on_drop(method = "...")]`, and is an associated function taking
+/// the fields in the order they are declared. Each field's type is wrapped in a `ManuallyDrop`, so
+/// that the generated drop glue can move the fields out and hand them over without the caller
+/// writing any unsafe code. Reading and writing fields works as before through `Deref`, but they
+/// are initialized with `ManuallyDrop::new`.
+///
+/// Rust doesn't allow moving fields out of a type that implements `Drop`, so the struct can't be
+/// destructured. Implement `ondrop::DropByValue` and wrap values in `ondrop::Owned` instead if that
+/// is needed.
+///
+/// # Examples
+///
+/// ```
+/// use ondrop::on_drop;
+/// use std::mem::ManuallyDrop;
+/// use std::sync::mpsc::{channel, Sender};
+///
+/// #[on_drop(method = "give_back")]
+/// struct Lease<T> {
+///     item: T,
+///     owner: Sender<T>,
+/// }
+///
+/// impl<T> Lease<T> {
+///     fn new(item: T, owner: Sender<T>) -> Self {
+///         Lease {
+///             item: ManuallyDrop::new(item),
+///             owner: ManuallyDrop::new(owner),
+///         }
+///     }
+///
+///     fn give_back(item: T, owner: Sender<T>) {
+///         owner.send(item).unwrap();
+///     }
+/// }
+///
+/// let (tx, rx) = channel();
+/// {
+///     let lease = Lease::new(String::from("car"), tx);
+///     assert_eq!(*lease.item, "car");
+/// }
+/// assert_eq!(rx.recv().unwrap(), "car");
+/// ```
+///
+/// Tuple and unit structs work too:
+///
+/// ```
+/// use ondrop::on_drop;
+/// use std::cell::Cell;
+/// use std::mem::ManuallyDrop;
+///
+/// #[on_drop(method = "release")]
+/// struct Permit<'a>(&'a Cell<u32>);
+///
+/// impl<'a> Permit<'a> {
+///     fn release(available: &'a Cell<u32>) {
+///         available.set(available.get() + 1);
+///     }
+/// }
+///
+/// let available = Cell::new(0);
+/// drop(Permit(ManuallyDrop::new(&available)));
+/// assert_eq!(available.get(), 1);
+/// ```
+///
+/// The method must be named:
+///
+/// ```compile_fail
+/// #[ondrop::on_drop]
+/// struct Lease;
+/// ```
+#[proc_macro_attribute]
+pub fn on_drop(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand_on_drop(attr, item) {
+        Ok(output) => output,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+fn expand_on_drop(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    let method = parse_on_drop_args(attr)?;
+    let method = if method.contains("::") {
+        method
+    } else {
+        format!("Self::{}", method)
+    };
+
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let mut i = 0;
+
+    // Outer attributes, visibility and the item keyword.
+    loop {
+        match tokens.get(i) {
+            Some(TokenTree::Punct(p)) if p.as_char() == '#' => i += 2,
+            Some(TokenTree::Ident(ident)) => {
+                let ident = ident.to_string();
+                i += 1;
+                if ident == "struct" {
+                    break;
+                }
+                if ident == "enum" || ident == "union" {
+                    return Err("#[on_drop] can only be applied to structs".into());
+                }
+            }
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => i += 1,
+            _ => return Err("#[on_drop] can only be applied to structs".into()),
+        }
+    }
+
+    let name = match tokens.get(i) {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a type name".into()),
+    };
+    i += 1;
+
+    let (impl_generics, ty_generics) = match tokens.get(i) {
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            let (params, end) = split_generics(&tokens, i + 1);
+            i = end;
+            let impl_params: Vec<String> = params.iter().map(|p| impl_param(p)).collect();
+            let ty_params: Vec<String> = params.iter().map(|p| ty_param(p)).collect();
+            (
+                format!("<{}>", impl_params.join(", ")),
+                format!("<{}>", ty_params.join(", ")),
+            )
+        }
+        _ => (String::new(), String::new()),
+    };
+
+    let where_clause = tokens[i..]
+        .iter()
+        .position(|t| matches!(t, TokenTree::Ident(ident) if ident.to_string() == "where"))
+        .map(|start| {
+            let clause: TokenStream = tokens[i + start..]
+                .iter()
+                .take_while(|t| match t {
+                    TokenTree::Group(g) => g.delimiter() != Delimiter::Brace,
+                    TokenTree::Punct(p) => p.as_char() != ';',
+                    _ => true,
+                })
+                .cloned()
+                .collect();
+            clause.to_string()
+        })
+        .unwrap_or_default();
+
+    // A tuple struct's fields come straight after the generics, a named struct's after the where
+    // clause, and a unit struct has none.
+    let body = match tokens.get(i) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => Some(i),
+        _ => tokens[i..]
+            .iter()
+            .position(|t| matches!(t, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace))
+            .map(|start| i + start),
+    };
+
+    let mut fields = Vec::new();
+    if let Some(body) = body {
+        let group = match &tokens[body] {
+            TokenTree::Group(g) => g.clone(),
+            _ => unreachable!(),
+        };
+        let named = group.delimiter() == Delimiter::Brace;
+        let mut wrapped = Vec::new();
+        for (n, field) in split_fields(group.stream()).into_iter().enumerate() {
+            let (ty_start, field_name) = field_type_start(&field, named)?;
+            fields.push(field_name.unwrap_or_else(|| n.to_string()));
+            wrapped.push(concat(vec![
+                field[..ty_start].iter().cloned().collect(),
+                parse("::core::mem::ManuallyDrop<"),
+                field[ty_start..].iter().cloned().collect(),
+                parse(">"),
+            ]));
+        }
+        let mut stream = TokenStream::new();
+        for field in wrapped {
+            stream.extend(field);
+            stream.extend(parse(","));
+        }
+        let mut new_group = Group::new(group.delimiter(), stream);
+        new_group.set_span(group.span());
+        tokens[body] = TokenTree::Group(new_group);
+    }
+
+    let drop_body = if fields.is_empty() {
+        format!("{}()", method)
+    } else {
+        let bindings: Vec<String> = (0..fields.len())
+            .map(|n| format!("__ondrop_{}", n))
+            .collect();
+        let takes: Vec<String> = fields
+            .iter()
+            .map(|f| format!("::core::mem::ManuallyDrop::take(&mut self.{})", f))
+            .collect();
+        format!(
+            "let ({},) = unsafe {{ ({},) }}; {}({})",
+            bindings.join(", "),
+            takes.join(", "),
+            method,
+            bindings.join(", ")
+        )
+    };
+
+    let mut output: TokenStream = tokens.into_iter().collect();
+    output.extend(
+        format!(
+            "impl{} ::core::ops::Drop for {}{} {} {{ \
+                 fn drop(&mut self) {{ {} }} \
+             }}",
+            impl_generics, name, ty_generics, where_clause, drop_body
+        )
+        .parse::<TokenStream>()
+        .map_err(|_| format!("invalid on_drop method: {}", method))?,
+    );
+    Ok(output)
+}
+
+/// Parses the arguments of an `#[on_drop(method = "...")]` attribute.
+fn parse_on_drop_args(args: TokenStream) -> Result<String, String> {
+    let args: Vec<TokenTree> = args.into_iter().collect();
+    match &args[..] {
+        [TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Literal(lit)]
+            if key.to_string() == "method" && eq.as_char() == '=' =>
+        {
+            let lit = lit.to_string();
+            if lit.len() > 2 && lit.starts_with('"') && lit.ends_with('"') {
+                Ok(lit[1..lit.len() - 1].to_string())
+            } else {
+                Err("expected the method name as a string literal".into())
+            }
+        }
+        _ => Err("expected #[on_drop(method = \"...\")]".into()),
+    }
+}
+
+/// Splits the contents of a struct body at top-level commas.
+fn split_fields(body: TokenStream) -> Vec<Vec<TokenTree>> {
+    let tokens: Vec<TokenTree> = body.into_iter().collect();
+    let mut fields = vec![Vec::new()];
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if let Some(d) = angle_bracket(&tokens, i) {
+            depth += d;
+        } else if let TokenTree::Punct(p) = token {
+            if p.as_char() == ',' && depth == 0 {
+                fields.push(Vec::new());
+                continue;
+            }
+        }
+        fields.last_mut().unwrap().push(token.clone());
+    }
+    fields.retain(|f| !f.is_empty());
+    fields
+}
+
+/// Returns the index at which a field's type starts, and the field's name if it has one.
+fn field_type_start(field: &[TokenTree], named: bool) -> Result<(usize, Option<String>), String> {
+    let mut i = 0;
+    // Attributes and visibility.
+    loop {
+        match field.get(i) {
+            Some(TokenTree::Punct(p)) if p.as_char() == '#' => i += 2,
+            Some(t) if is_ident(t, "pub") => {
+                i += 1;
+                if let Some(TokenTree::Group(g)) = field.get(i) {
+                    if g.delimiter() == Delimiter::Parenthesis {
+                        i += 1;
+                    }
+                }
+            }
+            _ => break,
+        }
+    }
+    if !named {
+        return Ok((i, None));
+    }
+    match (field.get(i), field.get(i + 1)) {
+        (Some(TokenTree::Ident(name)), Some(TokenTree::Punct(colon))) if colon.as_char() == ':' => {
+            Ok((i + 2, Some(name.to_string())))
+        }
+        _ => Err("expected a field name".into()),
+    }
+}
+
+/// Runs a cleanup on every exit from a function: `return`, `?`, falling off the end, or panic.
+///
+/// The attribute's argument is either an expression, run on every exit, or a closure taking one
+/// argument. The closure is passed `Some(&value)` with the function's return value when it
+/// returns normally, and `None` if it panics. Behind the scenes the function body is wrapped in
+/// a closure, so `return` and `?` work as usual.
+///
+/// Like the guards it replaces, the cleanup may borrow the function's arguments, but not in a
+/// way that conflicts with what the body does with them. Multiple `#[cleanup]` attributes run
+/// top to bottom. `async` functions aren't supported.
+///
+/// # Examples
+///
+/// ```
+/// use ondrop::cleanup;
+/// use std::cell::Cell;
+///
+/// #[cleanup(calls.set(calls.get() + 1))]
+/// fn parse(calls: &Cell<u32>, s: &str) -> Result<u32, std::num::ParseIntError> {
+///     let n = s.parse()?;
+///     if n == 0 {
+///         return Ok(1);
+///     }
+///     Ok(n)
+/// }
+///
+/// let calls = Cell::new(0);
+/// assert_eq!(parse(&calls, "0"), Ok(1));
+/// assert!(parse(&calls, "x").is_err());
+/// assert_eq!(parse(&calls, "7"), Ok(7));
+/// assert_eq!(calls.get(), 3);
+/// ```
+///
+/// Seeing the return value:
+///
+/// ```
+/// use ondrop::cleanup;
+/// use std::cell::RefCell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// #[cleanup(|ret| log.borrow_mut().push(ret.copied()))]
+/// fn div(log: &RefCell<Vec<Option<u32>>>, a: u32, b: u32) -> u32 {
+///     a / b
+/// }
+///
+/// let log = RefCell::new(Vec::new());
+/// assert_eq!(div(&log, 6, 3), 2);
+/// assert!(catch_unwind(AssertUnwindSafe(|| div(&log, 1, 0))).is_err());
+/// assert_eq!(*log.borrow(), [Some(2), None]);
+/// ```
+#[proc_macro_attribute]
+pub fn cleanup(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand_cleanup(attr, item) {
+        Ok(output) => output,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+fn expand_cleanup(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    let mut sig: Vec<TokenTree> = item.into_iter().collect();
+    let body = match sig.pop() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        _ => return Err("#[cleanup] can only be applied to functions with a body".into()),
+    };
+
+    let fn_pos = sig
+        .iter()
+        .position(|t| is_ident(t, "fn"))
+        .ok_or("#[cleanup] can only be applied to functions")?;
+    if sig[..fn_pos].iter().any(|t| is_ident(t, "async")) {
+        return Err("#[cleanup] doesn't support async functions".into());
+    }
+
+    // The parameter list is the first parenthesized group outside of the generics.
+    let mut depth = 0;
+    let params_pos = (fn_pos + 1..sig.len())
+        .find(|&i| match angle_bracket(&sig, i) {
+            Some(d) => {
+                depth += d;
+                false
+            }
+            None => {
+                depth == 0
+                    && matches!(&sig[i], TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis)
+            }
+        })
+        .ok_or("expected function parameters")?;
+
+    let ret: TokenStream = match (sig.get(params_pos + 1), sig.get(params_pos + 2)) {
+        (Some(TokenTree::Punct(a)), Some(TokenTree::Punct(b)))
+            if a.as_char() == '-' && b.as_char() == '>' =>
+        {
+            sig[params_pos + 3..]
+                .iter()
+                .take_while(|t| !is_ident(t, "where"))
+                .cloned()
+                .collect()
+        }
+        _ => parse("()"),
+    };
+    // Closure return types can't be `impl Trait`, so leave those to inference.
+    let ret_is_impl = ret.to_string().split_whitespace().any(|w| w == "impl");
+
+    let attr: Vec<TokenTree> = attr.into_iter().collect();
+    let arity = closure_arity(&attr);
+    let ends_with_semi = matches!(attr.last(), Some(TokenTree::Punct(p)) if p.as_char() == ';');
+    let attr: TokenStream = attr.into_iter().collect();
+    let new_body =
+        match arity {
+            None => {
+                let mut expr = attr;
+                if !ends_with_semi {
+                    expr.extend(parse(";"));
+                }
+                concat(vec![
+                    parse("let __ondrop_cleanup = ::ondrop::OnDrop::new"),
+                    group(
+                        Delimiter::Parenthesis,
+                        concat(vec![parse("||"), group(Delimiter::Brace, expr)]),
+                    ),
+                    parse(";"),
+                    body.stream(),
+                ])
+            }
+            Some(0) => concat(vec![
+                parse("let __ondrop_cleanup = ::ondrop::OnDrop::new"),
+                group(Delimiter::Parenthesis, attr),
+                parse(";"),
+                body.stream(),
+            ]),
+            Some(_) => {
+                let hint_ret = if ret_is_impl { parse("_") } else { ret.clone() };
+                let closure_ret = if ret_is_impl {
+                    TokenStream::new()
+                } else {
+                    concat(vec![parse("->"), ret])
+                };
+                concat(vec![
+                parse(
+                    "fn __ondrop_hint<R, F: FnOnce(::core::option::Option<&R>)>(f: F) -> F { f }
+                     let __ondrop_cleanup = ::ondrop::DropWith::new",
+                ),
+                group(
+                    Delimiter::Parenthesis,
+                    concat(vec![
+                        parse("__ondrop_hint::<"),
+                        hint_ret,
+                        parse(", _>"),
+                        group(Delimiter::Parenthesis, attr),
+                        parse(", |f| f(::core::option::Option::None)"),
+                    ]),
+                ),
+                parse(
+                    "; #[allow(clippy::redundant_closure_call)]
+                     let __ondrop_ret = ",
+                ),
+                group(
+                    Delimiter::Parenthesis,
+                    concat(vec![parse("||"), closure_ret, TokenTree::Group(body).into()]),
+                ),
+                parse(
+                    "();
+                     (::ondrop::DropWith::into_inner(__ondrop_cleanup))(
+                         ::core::option::Option::Some(&__ondrop_ret),
+                     );
+                     __ondrop_ret",
+                ),
+            ])
+            }
+        };
+
+    let mut output: TokenStream = sig.into_iter().collect();
+    output.extend(group(Delimiter::Brace, new_body));
+    Ok(output)
+}
+
+/// If the tokens are a closure, returns whether it takes zero (`Some(0)`) or more (`Some(1)`)
+/// arguments.
+fn closure_arity(tokens: &[TokenTree]) -> Option<usize> {
+    let tokens = match tokens {
+        [first, rest @ ..] if is_ident(first, "move") => rest,
+        _ => tokens,
+    };
+    match tokens {
+        [TokenTree::Punct(a), TokenTree::Punct(b), ..]
+            if a.as_char() == '|' && b.as_char() == '|' =>
+        {
+            Some(0)
+        }
+        [TokenTree::Punct(a), ..] if a.as_char() == '|' => Some(1),
+        _ => None,
+    }
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn parse(s: &str) -> TokenStream {
+    s.parse().unwrap()
+}
+
+fn group(delimiter: Delimiter, stream: TokenStream) -> TokenStream {
+    TokenTree::Group(Group::new(delimiter, stream)).into()
+}
+
+fn concat(streams: Vec<TokenStream>) -> TokenStream {
+    streams.into_iter().collect()
+}
+
+/// Returns `Some(+1)` or `Some(-1)` if `tokens[i]` opens or closes a pair of angle brackets.
+///
+/// The `>` of a `->` doesn't count.
+fn angle_bracket(tokens: &[TokenTree], i: usize) -> Option<i32> {
+    match &tokens[i] {
+        TokenTree::Punct(p) if p.as_char() == '<' => Some(1),
+        TokenTree::Punct(p) if p.as_char() == '>' => match i.checked_sub(1).map(|j| &tokens[j]) {
+            Some(TokenTree::Punct(prev))
+                if prev.as_char() == '-' && prev.spacing() == Spacing::Joint =>
+            {
+                None
+            }
+            _ => Some(-1),
+        },
+        _ => None,
+    }
+}
+
+/// Splits generic parameters, starting just after the opening `<`, at top-level commas.
+///
+/// Returns the parameters and the index just past the closing `>`.
+fn split_generics(tokens: &[TokenTree], start: usize) -> (Vec<Vec<TokenTree>>, usize) {
+    let mut params = vec![Vec::new()];
+    let mut depth = 0;
+    let mut i = start;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        i += 1;
+        match angle_bracket(tokens, i - 1) {
+            Some(-1) if depth == 0 => break,
+            Some(d) => depth += d,
+            None => {}
+        }
+        if depth == 0 {
+            if let TokenTree::Punct(p) = token {
+                if p.as_char() == ',' {
+                    params.push(Vec::new());
+                    continue;
+                }
+            }
+        }
+        params.last_mut().unwrap().push(token.clone());
+    }
+    params.retain(|p| !p.is_empty());
+    (params, i)
+}
+
+/// A generic parameter as written in `impl<...>`: without any default.
+fn impl_param(param: &[TokenTree]) -> String {
+    let mut depth = 0;
+    let mut end = param.len();
+    for (i, token) in param.iter().enumerate() {
+        if let Some(d) = angle_bracket(param, i) {
+            depth += d;
+        } else if let TokenTree::Punct(p) = token {
+            if p.as_char() == '=' && depth == 0 {
+                end = i;
+                break;
+            }
+        }
+    }
+    param[..end]
+        .iter()
+        .cloned()
+        .collect::<TokenStream>()
+        .to_string()
+}
+
+/// A generic parameter as written in the type's arguments: just its name.
+fn ty_param(param: &[TokenTree]) -> String {
+    match param {
+        [TokenTree::Punct(p), ..] if p.as_char() == '\'' => param[..2]
+            .iter()
+            .cloned()
+            .collect::<TokenStream>()
+            .to_string(),
+        [TokenTree::Ident(kw), name, ..] if kw.to_string() == "const" => name.to_string(),
+        [name, ..] => name.to_string(),
+        [] => String::new(),
+    }
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?});", msg).parse().unwrap()
+}
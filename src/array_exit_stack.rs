@@ -0,0 +1,244 @@
+//! A fixed-capacity stack of cleanups that doesn't allocate.
+
+use core::fmt;
+use core::mem;
+
+use crate::inline::InlineFnOnce;
+use crate::site::Site;
+use crate::unwind::unwind;
+
+/// A stack of up to `N` closures that are called in reverse order when dropped.
+///
+/// The allocation-free counterpart of `ExitStack`: closures are type-erased and stored inline,
+/// in slots of `S` bytes each. Pushing a closure that is larger than `S` bytes, or that needs
+/// more than 16-byte alignment, is a compile-time error; pushing onto a full stack returns the
+/// closure in a [`CapacityError`].
+///
+/// As with `ExitStack`, if one of the closures panics the remaining ones are still called as the
+/// panic unwinds.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::ArrayExitStack;
+/// use std::cell::RefCell;
+///
+/// let log = RefCell::new(Vec::new());
+/// {
+///     let mut stack = ArrayExitStack::<4>::new();
+///     for i in 0..3 {
+///         let log = &log;
+///         stack.push(move || log.borrow_mut().push(i)).unwrap();
+///     }
+/// }
+/// assert_eq!(*log.borrow(), [2, 1, 0]);
+/// ```
+///
+/// Closures that don't fit in a slot are rejected at compile time:
+///
+/// ```compile_fail
+/// # use ondrop::ArrayExitStack;
+/// let big = [0u8; 64];
+/// let mut stack = ArrayExitStack::<1, 32>::new();
+/// stack.push(move || drop(big)).unwrap();
+/// ```
+pub struct ArrayExitStack<'a, const N: usize, const S: usize = 32> {
+    slots: [Option<InlineFnOnce<'a, S>>; N],
+    len: usize,
+    site: Site,
+}
+
+impl<'a, const N: usize, const S: usize> ArrayExitStack<'a, N, S> {
+    const EMPTY: Option<InlineFnOnce<'a, S>> = None;
+
+    /// Creates a new, empty, `ArrayExitStack`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY; N],
+            len: 0,
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Pushes a closure onto the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns the closure, uncalled, if the stack is full.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::ArrayExitStack;
+    /// let mut stack = ArrayExitStack::<1>::new();
+    /// stack.push(|| ()).unwrap();
+    /// let err = stack.push(|| panic!()).unwrap_err();
+    /// drop(err.into_inner()); // no panic
+    /// ```
+    pub fn push<F: FnOnce() + 'a>(&mut self, f: F) -> Result<(), CapacityError<F>> {
+        if self.len == N {
+            return Err(CapacityError { f });
+        }
+        self.slots[self.len] = Some(InlineFnOnce::new(f));
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Moves all pending closures to a new stack, leaving this one empty.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn pop_all(&mut self) -> Self {
+        let mut popped = Self::new();
+        mem::swap(&mut self.slots, &mut popped.slots);
+        popped.len = mem::take(&mut self.len);
+        popped
+    }
+
+    /// Calls all pending closures now, in reverse order.
+    pub fn close(mut self) {
+        self.unwind()
+    }
+
+    /// Drops all pending closures without calling them.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::ArrayExitStack;
+    /// let mut stack = ArrayExitStack::<1>::new();
+    /// stack.push(|| panic!()).unwrap();
+    /// stack.cancel(); // no panic
+    /// ```
+    pub fn cancel(mut self) {
+        while let Some(f) = self.pop() {
+            drop(f);
+        }
+    }
+
+    /// Returns the number of pending closures.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no pending closures.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if no more closures can be pushed.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    fn pop(&mut self) -> Option<InlineFnOnce<'a, S>> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.slots[self.len].take()
+    }
+
+    fn unwind(&mut self) {
+        unwind(self, Self::pop, |_, f| f.call(), |_, f| f.call())
+    }
+}
+
+impl<const N: usize, const S: usize> Default for ArrayExitStack<'_, N, S> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const S: usize> fmt::Debug for ArrayExitStack<'_, N, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArrayExitStack")
+            .field("len", &self.len)
+            .field("capacity", &N)
+            .field("site", &self.site)
+            .finish()
+    }
+}
+
+impl<const N: usize, const S: usize> Drop for ArrayExitStack<'_, N, S> {
+    fn drop(&mut self) {
+        self.unwind()
+    }
+}
+
+/// The error returned by [`ArrayExitStack::push`] when the stack is full.
+///
+/// Holds the closure that could not be pushed.
+pub struct CapacityError<F> {
+    f: F,
+}
+
+impl<F> CapacityError<F> {
+    /// Unwraps the closure that could not be pushed.
+    pub fn into_inner(self) -> F {
+        self.f
+    }
+}
+
+impl<F> fmt::Debug for CapacityError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CapacityError").finish_non_exhaustive()
+    }
+}
+
+impl<F> fmt::Display for CapacityError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("exit stack is full")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F> std::error::Error for CapacityError<F> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    /// Make sure every closure is called, and deallocated once, even if one of them panics.
+    fn runs_remaining_closures_after_panic() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let mut stack = ArrayExitStack::<4>::new();
+            for i in 0..4 {
+                let (log, token) = (&log, check.token());
+                stack
+                    .push(move || {
+                        log.borrow_mut().push(i);
+                        drop(token);
+                        if i == 2 {
+                            panic!();
+                        }
+                    })
+                    .unwrap();
+            }
+            let token = check.token();
+            assert!(stack.push(move || drop(token)).is_err());
+        }));
+        assert!(r.is_err());
+        assert_eq!(*log.borrow(), [3, 2, 1, 0]);
+        assert!(check.all_dropped());
+    }
+
+    #[test]
+    /// Cancelled closures must be deallocated without being called.
+    fn cancel_drops_closures() {
+        let check = DropCheck::new();
+        let mut stack = ArrayExitStack::<2>::new();
+        for _ in 0..2 {
+            let token = check.token();
+            stack.push(move || panic!("{:?}", token)).unwrap();
+        }
+        stack.pop_all().cancel();
+        assert!(check.all_dropped());
+    }
+}
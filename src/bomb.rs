@@ -0,0 +1,270 @@
+//! Values that must be explicitly consumed rather than dropped.
+
+use core::fmt;
+use core::mem::{self, ManuallyDrop};
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr;
+
+/// What happens when a value that must be consumed is dropped instead.
+///
+/// Nothing happens if the thread is already unwinding, as the value was most likely dropped
+/// because of the panic rather than by mistake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Detonation {
+    /// Panic. The default in debug builds.
+    Panic,
+
+    /// Print a message to stderr, then abort the process.
+    ///
+    /// Without the `std` feature there is no way to abort, so this panics instead.
+    Abort,
+
+    /// Print a message to stderr. The default in release builds with `std`.
+    ///
+    /// Without the `std` feature there is nowhere to print to, so this does nothing.
+    Log,
+
+    /// Do nothing. The default in release builds without `std`.
+    Ignore,
+}
+
+impl Default for Detonation {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Detonation::Panic
+        } else {
+            #[cfg(feature = "std")]
+            {
+                Detonation::Log
+            }
+            #[cfg(not(feature = "std"))]
+            {
+                Detonation::Ignore
+            }
+        }
+    }
+}
+
+impl Detonation {
+    /// Reports that something was dropped without being consumed, and where it was created if
+    /// known.
+    pub(crate) fn detonate(self, what: fmt::Arguments, location: Option<&Location>) {
+        #[cfg(feature = "std")]
+        {
+            if std::thread::panicking() {
+                return;
+            }
+        }
+        match self {
+            Detonation::Panic => panic!("{}{} was dropped", what, Created(location)),
+            #[cfg(feature = "std")]
+            Detonation::Abort => {
+                std::eprintln!("{}{} was dropped", what, Created(location));
+                std::process::abort()
+            }
+            #[cfg(not(feature = "std"))]
+            Detonation::Abort => panic!("{}{} was dropped", what, Created(location)),
+            #[cfg(feature = "std")]
+            Detonation::Log => std::eprintln!("{}{} was dropped", what, Created(location)),
+            #[cfg(not(feature = "std"))]
+            Detonation::Log => {}
+            Detonation::Ignore => {}
+        }
+    }
+}
+
+/// Formats as ` created at <location>`, if the location is known.
+struct Created<'a>(Option<&'a Location<'a>>);
+
+impl fmt::Display for Created<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(location) => write!(f, " created at {}", location),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Wraps a value that must be explicitly consumed with [`into_inner`](Self::into_inner).
+///
+/// Dropping a `MustConsume` instead detonates it according to its [`Detonation`], reporting
+/// where it was created. Useful for protocol states that must be explicitly completed.
+///
+/// # Examples
+///
+/// ```should_panic
+/// # use ondrop::{Detonation, MustConsume};
+/// struct Handshake;
+///
+/// let handshake = MustConsume::with_detonation(Handshake, Detonation::Panic);
+/// drop(handshake); // panics
+/// ```
+pub struct MustConsume<T> {
+    value: ManuallyDrop<T>,
+    detonation: Detonation,
+    location: &'static Location<'static>,
+}
+
+impl<T> MustConsume<T> {
+    /// Wraps a value, using the default [`Detonation`].
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self::with_detonation(value, Detonation::default())
+    }
+
+    /// Wraps a value, using the given [`Detonation`].
+    #[track_caller]
+    pub fn with_detonation(value: T, detonation: Detonation) -> Self {
+        Self {
+            value: ManuallyDrop::new(value),
+            detonation,
+            location: Location::caller(),
+        }
+    }
+
+    /// Consumes the wrapper, returning the value.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::MustConsume;
+    /// let n = MustConsume::new(42);
+    /// assert_eq!(n.into_inner(), 42); // no panic
+    /// ```
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read(&*this.value) }
+    }
+
+    /// Returns where the wrapper was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl<T> Deref for MustConsume<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for MustConsume<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MustConsume<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MustConsume")
+            .field("value", &*self.value)
+            .field("detonation", &self.detonation)
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+impl<T> Drop for MustConsume<T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.value) };
+        self.detonation.detonate(
+            format_args!("MustConsume<{}>", core::any::type_name::<T>()),
+            Some(self.location),
+        )
+    }
+}
+
+/// Detonates if dropped without being [`defuse`](Self::defuse)d.
+///
+/// Handy in tests, to assert that a code path was taken.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::DropBomb;
+/// let bomb = DropBomb::new("callback was never called");
+/// let callback = move || bomb.defuse();
+/// callback();
+/// ```
+#[derive(Debug)]
+pub struct DropBomb {
+    message: &'static str,
+    detonation: Detonation,
+    location: &'static Location<'static>,
+}
+
+impl DropBomb {
+    /// Creates a new `DropBomb` with a message, using the default [`Detonation`].
+    #[track_caller]
+    pub fn new(message: &'static str) -> Self {
+        Self::with_detonation(message, Detonation::default())
+    }
+
+    /// Creates a new `DropBomb` with a message, using the given [`Detonation`].
+    #[track_caller]
+    pub fn with_detonation(message: &'static str, detonation: Detonation) -> Self {
+        Self {
+            message,
+            detonation,
+            location: Location::caller(),
+        }
+    }
+
+    /// Defuses the bomb.
+    pub fn defuse(self) {
+        mem::forget(self)
+    }
+
+    /// Returns where the bomb was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl Drop for DropBomb {
+    fn drop(&mut self) {
+        self.detonation.detonate(
+            format_args!("DropBomb ({})", self.message),
+            Some(self.location),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+
+    #[test]
+    #[should_panic(expected = "MustConsume<dropcheck::DropToken> created at src/bomb.rs")]
+    fn must_consume_panics_when_dropped() {
+        let check = DropCheck::new();
+        let token = MustConsume::with_detonation(check.token(), Detonation::Panic);
+        drop(token);
+    }
+
+    #[test]
+    /// The wrapped value must be deallocated whether or not the wrapper detonates.
+    fn must_consume_drops_value_once() {
+        let check = DropCheck::new();
+
+        let token = MustConsume::with_detonation(check.token(), Detonation::Ignore);
+        drop(token.into_inner());
+        assert!(check.all_dropped());
+
+        let token = MustConsume::with_detonation(check.token(), Detonation::Ignore);
+        drop(token);
+        assert!(check.all_dropped());
+    }
+
+    #[test]
+    #[should_panic(expected = "DropBomb (boom) created at")]
+    fn drop_bomb_panics_when_dropped() {
+        let bomb = DropBomb::with_detonation("boom", Detonation::Panic);
+        DropBomb::new("defused").defuse();
+        drop(bomb);
+    }
+}
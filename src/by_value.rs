@@ -0,0 +1,122 @@
+//! Destructors that take `self` by value.
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+/// A destructor that takes ownership of the value being destroyed.
+///
+/// Unlike `Drop::drop`, which only gets `&mut self`, `drop_by_value` can move fields out of
+/// `self`. Wrap the value in an [`Owned`] to have it called automatically.
+pub trait DropByValue {
+    /// Destroys the value.
+    fn drop_by_value(self);
+}
+
+/// Calls [`DropByValue::drop_by_value`] on the wrapped value when dropped.
+///
+/// This is the `ManuallyDrop` and `ptr::read` trick [`OnDrop`](struct@crate::OnDrop) uses to call
+/// its closure by value, packaged up so your own types don't need any unsafe code.
+///
+/// # Examples
+///
+/// Returning a buffer to a pool:
+///
+/// ```
+/// # use ondrop::{DropByValue, Owned};
+/// use std::cell::RefCell;
+///
+/// struct PooledBuf<'a> {
+///     buf: Vec<u8>,
+///     pool: &'a RefCell<Vec<Vec<u8>>>,
+/// }
+///
+/// impl DropByValue for PooledBuf<'_> {
+///     fn drop_by_value(self) {
+///         let PooledBuf { mut buf, pool } = self;
+///         buf.clear();
+///         pool.borrow_mut().push(buf);
+///     }
+/// }
+///
+/// let pool = RefCell::new(vec![Vec::with_capacity(1024)]);
+/// {
+///     let buf = pool.borrow_mut().pop().unwrap();
+///     let mut pooled = Owned::new(PooledBuf { buf, pool: &pool });
+///     pooled.buf.extend_from_slice(b"hello");
+/// }
+/// assert_eq!(pool.borrow().len(), 1);
+/// assert!(pool.borrow()[0].is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Owned<T: DropByValue>(ManuallyDrop<T>);
+
+impl<T: DropByValue> Owned<T> {
+    /// Creates a new `Owned` from a value.
+    pub fn new(value: T) -> Self {
+        Self(ManuallyDrop::new(value))
+    }
+
+    /// Unwraps the value without calling `drop_by_value`.
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read(&*this.0) }
+    }
+}
+
+impl<T: DropByValue> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DropByValue> DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DropByValue> Drop for Owned<T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            let value: T = ptr::read(&*self.0);
+            value.drop_by_value()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::{DropCheck, DropToken};
+
+    struct Tokens(DropToken, DropToken);
+
+    impl DropByValue for Tokens {
+        fn drop_by_value(self) {
+            let Tokens(a, b) = self;
+            drop(b);
+            drop(a);
+        }
+    }
+
+    #[test]
+    /// Make sure the value is deallocated once and only once.
+    fn drops_value_once() {
+        let check = DropCheck::new();
+
+        let owned = Owned::new(Tokens(check.token(), check.token()));
+        let Tokens(a, b) = owned.into_inner();
+        assert!(check.none_dropped());
+        drop((a, b));
+        assert!(check.all_dropped());
+
+        let owned = Owned::new(Tokens(check.token(), check.token()));
+        drop(owned);
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,134 @@
+//! Guards that own a value and hand it to the closure on drop.
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use crate::site::Site;
+
+/// Owns a value, and passes it by value to the wrapped closure when dropped.
+///
+/// While the guard is alive the value is available through `Deref` and `DerefMut`, so a resource
+/// and its release function can be kept together without losing access to the resource.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::DropWith;
+/// let mut released = Vec::new();
+/// {
+///     let mut buf = DropWith::new(Vec::new(), |buf| released = buf);
+///     buf.push(1u8);
+///     buf.push(2);
+///     assert_eq!(buf.len(), 2);
+/// }
+/// assert_eq!(released, [1, 2]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DropWith<T, F: FnOnce(T)> {
+    value: ManuallyDrop<T>,
+    f: ManuallyDrop<F>,
+    site: Site,
+}
+
+impl<T, F: FnOnce(T)> DropWith<T, F> {
+    /// Creates a new `DropWith` from a value and a closure.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(value: T, f: F) -> Self {
+        Self {
+            value: ManuallyDrop::new(value),
+            f: ManuallyDrop::new(f),
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Unwraps the value without calling the closure.
+    ///
+    /// The closure itself is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::DropWith;
+    /// let guard = DropWith::new(42, |_| panic!());
+    /// assert_eq!(guard.into_inner(), 42); // no panic
+    /// ```
+    pub fn into_inner(self) -> T {
+        self.into_parts().0
+    }
+
+    /// Unwraps both the value and the closure without calling the closure.
+    pub fn into_parts(self) -> (T, F) {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.site);
+            (
+                ManuallyDrop::take(&mut this.value),
+                ManuallyDrop::take(&mut this.f),
+            )
+        }
+    }
+}
+
+impl<T: Default, F: FnOnce(T) + Default> Default for DropWith<T, F> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(T::default(), F::default())
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for DropWith<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, F: FnOnce(T)> DerefMut for DropWith<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for DropWith<T, F> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            let value: T = ptr::read(&*self.value);
+            let f: F = ptr::read(&*self.f);
+            f(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+
+    #[test]
+    /// Make sure the value and the closure are each deallocated once and only once.
+    fn drops_value_and_closure_once() {
+        let check = DropCheck::new();
+        let (value, value_state) = check.pair();
+        let (token, closure_state) = check.pair();
+
+        let guard = DropWith::new(value, move |_value| drop(token));
+        drop(guard.into_inner());
+        assert!(value_state.is_dropped());
+        assert!(closure_state.is_dropped());
+
+        let (value, value_state) = check.pair();
+        let (token, closure_state) = check.pair();
+
+        let guard = DropWith::new(value, move |value| {
+            drop(value);
+            drop(token);
+        });
+        assert!(value_state.is_not_dropped());
+        drop(guard);
+        assert!(value_state.is_dropped());
+        assert!(closure_state.is_dropped());
+    }
+}
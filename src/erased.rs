@@ -0,0 +1,318 @@
+//! Nameable guards with the closure type erased.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::inline::{InlineFnOnce, Local, SendOnly};
+use crate::site::Site;
+use crate::{Cleanup, OnDrop};
+
+#[cfg(feature = "alloc")]
+macro_rules! boxed_on_drop {
+    ($(#[$attr:meta])* $name:ident, $($bounds:tt)*) => {
+        $(#[$attr])*
+        pub struct $name<'a>(OnDrop<Box<dyn FnOnce() $($bounds)* + 'a>>);
+
+        impl<'a> $name<'a> {
+            /// Creates a new guard from a closure, boxing it.
+            #[cfg_attr(feature = "track-caller", track_caller)]
+            pub fn new(f: impl FnOnce() $($bounds)* + 'a) -> Self {
+                Self(OnDrop::new(Box::new(f)))
+            }
+
+            /// Unwraps the boxed closure without calling it.
+            pub fn into_inner(self) -> Box<dyn FnOnce() $($bounds)* + 'a> {
+                self.0.into_inner()
+            }
+
+            /// Calls the closure now, consuming the guard.
+            ///
+            /// The closure is called even if the guard has been disarmed.
+            pub fn run(self) {
+                self.0.run()
+            }
+
+            /// Disarms the guard, so that dropping it will not call the closure.
+            pub fn disarm(&mut self) {
+                self.0.disarm()
+            }
+
+            /// Re-arms a disarmed guard, so that dropping it calls the closure again.
+            pub fn arm(&mut self) {
+                self.0.arm()
+            }
+
+            /// Returns `true` if dropping the guard will call the closure.
+            pub fn is_armed(&self) -> bool {
+                self.0.is_armed()
+            }
+        }
+
+        impl<'a, F: Cleanup $($bounds)* + 'a> From<OnDrop<F>> for $name<'a> {
+            /// Boxes the closure of an `OnDrop`, keeping whether or not it is armed.
+            fn from(guard: OnDrop<F>) -> Self {
+                let (f, armed, site) = guard.into_parts();
+                Self(OnDrop::from_parts(Box::new(move || f.cleanup()), armed, site))
+            }
+        }
+
+        impl fmt::Debug for $name<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("armed", &self.is_armed())
+                    .finish()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "alloc")]
+boxed_on_drop! {
+    /// An [`OnDrop`](struct@OnDrop) with a boxed closure, so that the type can be named.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ondrop::{BoxOnDrop, OnDrop};
+    /// struct Connection<'a> {
+    ///     on_close: BoxOnDrop<'a>,
+    /// }
+    ///
+    /// let mut closed = false;
+    /// let conn = Connection {
+    ///     on_close: OnDrop::new(|| closed = true).into(),
+    /// };
+    /// drop(conn);
+    /// assert!(closed);
+    /// ```
+    BoxOnDrop,
+}
+
+#[cfg(feature = "alloc")]
+boxed_on_drop! {
+    /// An [`OnDrop`](struct@OnDrop) with a boxed `Send` closure, so that the type can be named.
+    ///
+    /// Unlike [`BoxOnDrop`] this is `Send`, so it can be moved to, and dropped on, another
+    /// thread.
+    SendOnDrop, + Send
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<SendOnDrop<'a>> for BoxOnDrop<'a> {
+    fn from(guard: SendOnDrop<'a>) -> Self {
+        let (f, armed, site) = guard.0.into_parts();
+        Self(OnDrop::from_parts(f, armed, site))
+    }
+}
+
+macro_rules! inline_on_drop {
+    ($(#[$attr:meta])* $name:ident, $marker:ty, $new:ident, $($bounds:tt)*) => {
+        $(#[$attr])*
+        pub struct $name<'a, const N: usize = 32> {
+            f: ManuallyDrop<InlineFnOnce<'a, N, $marker>>,
+            armed: bool,
+            site: Site,
+        }
+
+        impl<'a, const N: usize> $name<'a, N> {
+            /// Creates a new guard from a closure, storing it inline.
+            #[cfg_attr(feature = "track-caller", track_caller)]
+            pub fn new(f: impl FnOnce() $($bounds)* + 'a) -> Self {
+                Self {
+                    f: ManuallyDrop::new(InlineFnOnce::$new(f)),
+                    armed: true,
+                    site: Site::caller::<Self>(),
+                }
+            }
+
+            /// Calls the closure now, consuming the guard.
+            ///
+            /// The closure is called even if the guard has been disarmed.
+            pub fn run(self) {
+                self.into_parts().0.call()
+            }
+
+            /// Disarms the guard, so that dropping it will not call the closure.
+            pub fn disarm(&mut self) {
+                self.armed = false;
+            }
+
+            /// Re-arms a disarmed guard, so that dropping it calls the closure again.
+            pub fn arm(&mut self) {
+                self.armed = true;
+            }
+
+            /// Returns `true` if dropping the guard will call the closure.
+            pub fn is_armed(&self) -> bool {
+                self.armed
+            }
+
+            fn into_parts(self) -> (InlineFnOnce<'a, N, $marker>, bool, Site) {
+                let mut this = ManuallyDrop::new(self);
+                unsafe { (ManuallyDrop::take(&mut this.f), this.armed, ptr::read(&this.site)) }
+            }
+        }
+
+        impl<'a, F: Cleanup $($bounds)* + 'a, const N: usize> From<OnDrop<F>> for $name<'a, N> {
+            /// Moves the closure of an `OnDrop` inline, keeping whether or not it is armed.
+            fn from(guard: OnDrop<F>) -> Self {
+                let (f, armed, site) = guard.into_parts();
+                Self {
+                    f: ManuallyDrop::new(InlineFnOnce::$new(move || f.cleanup())),
+                    armed,
+                    site,
+                }
+            }
+        }
+
+        impl<const N: usize> fmt::Debug for $name<'_, N> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("armed", &self.armed)
+                    .field("site", &self.site)
+                    .finish()
+            }
+        }
+
+        impl<const N: usize> Drop for $name<'_, N> {
+            fn drop(&mut self) {
+                let f = unsafe { ManuallyDrop::take(&mut self.f) };
+                if self.armed {
+                    f.call()
+                }
+            }
+        }
+    };
+}
+
+inline_on_drop! {
+    /// An [`OnDrop`](struct@OnDrop) whose closure is stored inline in `N` bytes, so that the type
+    /// can be named without allocating.
+    ///
+    /// Creating an `InlineOnDrop` from a closure larger than `N` bytes, or that needs more than
+    /// 16-byte alignment, is a compile-time error. As nothing is known about the closure, the
+    /// guard is neither `Send` nor `Sync`; see [`InlineSendOnDrop`] for one that is `Send`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ondrop::InlineOnDrop;
+    /// struct Session<'a> {
+    ///     on_end: InlineOnDrop<'a>,
+    /// }
+    ///
+    /// let mut ended = false;
+    /// let session = Session {
+    ///     on_end: InlineOnDrop::new(|| ended = true),
+    /// };
+    /// drop(session);
+    /// assert!(ended);
+    /// ```
+    InlineOnDrop, Local, new,
+}
+
+inline_on_drop! {
+    /// An [`OnDrop`](struct@OnDrop) whose `Send` closure is stored inline in `N` bytes, so that
+    /// the type can be named without allocating.
+    ///
+    /// Unlike [`InlineOnDrop`] this is `Send`, so it can be moved to, and dropped on, another
+    /// thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ondrop::InlineSendOnDrop;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let guard: InlineSendOnDrop = InlineSendOnDrop::new(move || tx.send("done").unwrap());
+    /// std::thread::spawn(move || drop(guard)).join().unwrap();
+    /// assert_eq!(rx.recv(), Ok("done"));
+    /// ```
+    ///
+    /// Closures that aren't `Send` are rejected:
+    ///
+    /// ```compile_fail
+    /// # use ondrop::InlineSendOnDrop;
+    /// let rc = std::rc::Rc::new(());
+    /// let guard: InlineSendOnDrop = InlineSendOnDrop::new(move || drop(rc));
+    /// ```
+    InlineSendOnDrop, SendOnly, new_send, + Send
+}
+
+impl<'a, const N: usize> From<InlineSendOnDrop<'a, N>> for InlineOnDrop<'a, N> {
+    fn from(guard: InlineSendOnDrop<'a, N>) -> Self {
+        let (f, armed, site) = guard.into_parts();
+        Self {
+            f: ManuallyDrop::new(f.into_local()),
+            armed,
+            site,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::Cell;
+
+    #[test]
+    /// Converted guards must keep their armed state, and deallocate their closures once.
+    fn conversions_keep_armed_state() {
+        let check = DropCheck::new();
+        let calls = &Cell::new(0);
+
+        let token = check.token();
+        let mut guard = OnDrop::new(move || {
+            drop(token);
+            calls.set(calls.get() + 1);
+        });
+        guard.disarm();
+        drop(InlineOnDrop::<32>::from(guard));
+        assert!(check.all_dropped());
+        assert_eq!(calls.get(), 0);
+
+        let token = check.token();
+        let guard = OnDrop::new(move || {
+            drop(token);
+            calls.set(calls.get() + 1);
+        });
+        drop(InlineOnDrop::<32>::from(guard));
+        assert!(check.all_dropped());
+        assert_eq!(calls.get(), 1);
+
+        let token = check.token();
+        let mut guard = SendOnDrop::new(move || drop(token));
+        guard.disarm();
+        drop(BoxOnDrop::from(guard));
+        assert!(check.all_dropped());
+
+        let token = check.token();
+        let mut guard = InlineSendOnDrop::<32>::new(move || drop(token));
+        guard.disarm();
+        drop(InlineOnDrop::from(guard));
+        assert!(check.all_dropped());
+
+        let token = check.token();
+        let guard = BoxOnDrop::from(OnDrop::new(move || {
+            drop(token);
+            calls.set(calls.get() + 1);
+        }));
+        guard.run();
+        assert!(check.all_dropped());
+        assert_eq!(calls.get(), 2);
+    }
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn send_on_drop_is_send() {
+        assert_send::<SendOnDrop<'static>>();
+        assert_send::<InlineSendOnDrop<'static>>();
+    }
+}
@@ -0,0 +1,336 @@
+//! A dynamic stack of cleanups.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::panic::Location;
+
+use crate::site::Site;
+use crate::unwind::unwind;
+use crate::{Detonation, DropReason};
+
+/// A stack of closures that are called in reverse order when dropped.
+///
+/// Where [`OnDrop`](struct@crate::OnDrop) handles one closure known at compile time, an `ExitStack`
+/// handles any number of them, registered at runtime. If one of the closures panics the remaining
+/// ones are still called as the panic unwinds, just as they would be for a series of `OnDrop`s.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::ExitStack;
+/// use std::cell::RefCell;
+///
+/// let log = RefCell::new(Vec::new());
+/// {
+///     let mut stack = ExitStack::new();
+///     for i in 0..3 {
+///         let log = &log;
+///         stack.push(move || log.borrow_mut().push(i));
+///     }
+/// }
+/// assert_eq!(*log.borrow(), [2, 1, 0]);
+/// ```
+pub struct ExitStack<'a> {
+    stack: Vec<Box<dyn FnOnce(DropReason) + 'a>>,
+    site: Site,
+}
+
+impl<'a> ExitStack<'a> {
+    /// Creates a new, empty, `ExitStack`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Pushes a closure onto the stack.
+    pub fn push(&mut self, f: impl FnOnce() + 'a) {
+        self.stack.push(Box::new(move |_| f()))
+    }
+
+    /// Pushes a closure that is passed the reason it is being called onto the stack.
+    ///
+    /// The reason is [`DropReason::StackClose`] if the stack is [`close`](Self::close)d, and
+    /// [`DropReason::Scope`] or [`DropReason::Unwinding`] if it is dropped. Closures still
+    /// pending after another one panics are passed `Unwinding`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::{DropReason, ExitStack};
+    /// use std::cell::Cell;
+    ///
+    /// let reason = Cell::new(None);
+    /// let mut stack = ExitStack::new();
+    /// stack.push_with_reason(|r| reason.set(Some(r)));
+    /// stack.close();
+    /// assert_eq!(reason.get(), Some(DropReason::StackClose));
+    /// ```
+    pub fn push_with_reason(&mut self, f: impl FnOnce(DropReason) + 'a) {
+        self.stack.push(Box::new(f))
+    }
+
+    /// Moves all pending closures to a new stack, leaving this one empty.
+    ///
+    /// Typically used once a series of acquisitions has succeeded, to hand their cleanups to
+    /// whatever now owns the resources.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::ExitStack;
+    /// let mut stack = ExitStack::new();
+    /// stack.push(|| panic!());
+    /// let pending = stack.pop_all();
+    /// drop(stack); // no panic
+    /// assert_eq!(pending.len(), 1);
+    /// # pending.cancel();
+    /// ```
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn pop_all(&mut self) -> ExitStack<'a> {
+        Self {
+            stack: mem::take(&mut self.stack),
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Calls all pending closures now, in reverse order.
+    pub fn close(mut self) {
+        self.unwind_to(0, DropReason::StackClose)
+    }
+
+    /// Drops all pending closures without calling them.
+    pub fn cancel(mut self) {
+        self.stack.clear()
+    }
+
+    /// Returns the number of pending closures.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns `true` if there are no pending closures.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Calls the closures pushed since the stack had `len` of them, in reverse order.
+    pub(crate) fn unwind_to(&mut self, len: usize, reason: DropReason) {
+        unwind(
+            &mut self.stack,
+            |stack| if stack.len() > len { stack.pop() } else { None },
+            |_, f| f(reason),
+            |_, f| f(DropReason::dropped()),
+        )
+    }
+}
+
+impl Default for ExitStack<'_> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ExitStack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExitStack")
+            .field("len", &self.stack.len())
+            .field("site", &self.site)
+            .finish()
+    }
+}
+
+impl Drop for ExitStack<'_> {
+    fn drop(&mut self) {
+        self.unwind_to(0, DropReason::dropped())
+    }
+}
+
+type ContextFn<'a, Ctx> = Box<dyn FnOnce(&mut Ctx) + 'a>;
+
+/// A stack of closures that are passed a shared context, and called in reverse order when the
+/// stack is closed.
+///
+/// Every closure gets `&mut Ctx`, so several cleanups can mutate the same state without
+/// wrapping it in a `RefCell`. As the context is only available to
+/// [`close`](Self::close), a stack that is dropped with closures still pending can't call them:
+/// they are dropped uncalled, and the stack detonates according to its [`Detonation`].
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::ExitStackWith;
+/// let mut open = vec!["a", "b"];
+/// let mut stack = ExitStackWith::new();
+/// stack.push(|open: &mut Vec<&str>| assert_eq!(open.pop(), Some("c")));
+/// stack.push(|open: &mut Vec<&str>| open.push("c"));
+/// stack.close(&mut open);
+/// assert_eq!(open, ["a", "b"]);
+/// ```
+pub struct ExitStackWith<'a, Ctx> {
+    stack: Vec<ContextFn<'a, Ctx>>,
+    detonation: Detonation,
+    site: Site,
+}
+
+impl<'a, Ctx> ExitStackWith<'a, Ctx> {
+    /// Creates a new, empty, `ExitStackWith`, using the default [`Detonation`].
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new() -> Self {
+        Self::with_detonation(Detonation::default())
+    }
+
+    /// Creates a new, empty, `ExitStackWith`, using the given [`Detonation`].
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn with_detonation(detonation: Detonation) -> Self {
+        Self {
+            stack: Vec::new(),
+            detonation,
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Pushes a closure onto the stack.
+    pub fn push(&mut self, f: impl FnOnce(&mut Ctx) + 'a) {
+        self.stack.push(Box::new(f))
+    }
+
+    /// Calls all pending closures now, in reverse order, passing each of them the context.
+    pub fn close(mut self, ctx: &mut Ctx) {
+        self.unwind(ctx)
+    }
+
+    /// Drops all pending closures without calling them.
+    pub fn cancel(mut self) {
+        self.stack.clear()
+    }
+
+    /// Returns the number of pending closures.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns `true` if there are no pending closures.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Returns where the stack was created.
+    ///
+    /// Always `None` unless the `track-caller` feature is enabled.
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.site.location()
+    }
+
+    fn unwind(&mut self, ctx: &mut Ctx) {
+        unwind(
+            &mut (&mut self.stack, ctx),
+            |(stack, _)| stack.pop(),
+            |(_, ctx), f| f(ctx),
+            |(_, ctx), f| f(ctx),
+        )
+    }
+}
+
+impl<Ctx> Default for ExitStackWith<'_, Ctx> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Ctx> fmt::Debug for ExitStackWith<'_, Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExitStackWith")
+            .field("len", &self.stack.len())
+            .field("detonation", &self.detonation)
+            .field("site", &self.site)
+            .finish()
+    }
+}
+
+impl<Ctx> Drop for ExitStackWith<'_, Ctx> {
+    fn drop(&mut self) {
+        let pending = mem::take(&mut self.stack).len();
+        if pending > 0 {
+            self.detonation.detonate(
+                format_args!(
+                    "ExitStackWith<{}> with {} pending closures",
+                    core::any::type_name::<Ctx>(),
+                    pending
+                ),
+                self.site.location(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    /// Make sure every closure is called, and deallocated once, even if one of them panics.
+    fn runs_remaining_closures_after_panic() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let mut stack = ExitStack::new();
+            for i in 0..4 {
+                let (log, token) = (&log, check.token());
+                stack.push(move || {
+                    log.borrow_mut().push(i);
+                    drop(token);
+                    if i == 2 {
+                        panic!();
+                    }
+                });
+            }
+        }));
+        assert!(r.is_err());
+        assert_eq!(*log.borrow(), [3, 2, 1, 0]);
+        assert!(check.all_dropped());
+    }
+
+    #[test]
+    /// Closures must be passed the context in reverse order, and deallocated once, even if one
+    /// of them panics.
+    fn with_context_runs_remaining_closures_after_panic() {
+        let check = DropCheck::new();
+        let mut log = Vec::new();
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let mut stack = ExitStackWith::new();
+            for i in 0..4 {
+                let token = check.token();
+                stack.push(move |log: &mut Vec<i32>| {
+                    log.push(i);
+                    drop(token);
+                    if i == 2 {
+                        panic!();
+                    }
+                });
+            }
+            stack.close(&mut log);
+        }));
+        assert!(r.is_err());
+        assert_eq!(log, [3, 2, 1, 0]);
+        assert!(check.all_dropped());
+    }
+
+    #[test]
+    #[should_panic(expected = "ExitStackWith<()> with 1 pending closures")]
+    fn with_context_detonates_when_dropped_with_pending_closures() {
+        let mut stack = ExitStackWith::with_detonation(Detonation::Panic);
+        stack.push(|_: &mut ()| ());
+        drop(stack);
+    }
+}
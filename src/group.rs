@@ -0,0 +1,173 @@
+//! Groups of cleanups that all run even if some of them panic.
+
+use core::mem::ManuallyDrop;
+use core::ptr;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::site::Site;
+
+/// A collection of closures that can all be called, even if some of them panic.
+///
+/// Implemented for tuples of up to twelve closures, arrays and `Vec`s. Closures are called
+/// first to last, the same order in which Rust drops the elements of a tuple or array.
+pub trait RunAll {
+    /// Calls every closure, catching panics.
+    ///
+    /// # Errors
+    ///
+    /// Returns the payload of the first panic, if any closure panicked. Payloads of later
+    /// panics are dropped.
+    fn run_all(self) -> Result<(), Box<dyn Any + Send>>;
+}
+
+fn run_one(f: impl FnOnce(), first_panic: &mut Option<Box<dyn Any + Send>>) {
+    // The closure is consumed either way, so it can't be observed in a broken state.
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        first_panic.get_or_insert(payload);
+    }
+}
+
+macro_rules! tuple_impls {
+    ($($name:ident)+) => {
+        impl<$($name: FnOnce()),+> RunAll for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn run_all(self) -> Result<(), Box<dyn Any + Send>> {
+                let ($($name,)+) = self;
+                let mut first_panic = None;
+                $(run_one($name, &mut first_panic);)+
+                first_panic.map_or(Ok(()), Err)
+            }
+        }
+    };
+}
+
+tuple_impls! { A }
+tuple_impls! { A B }
+tuple_impls! { A B C }
+tuple_impls! { A B C D }
+tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
+tuple_impls! { A B C D E F G }
+tuple_impls! { A B C D E F G H }
+tuple_impls! { A B C D E F G H I }
+tuple_impls! { A B C D E F G H I J }
+tuple_impls! { A B C D E F G H I J K }
+tuple_impls! { A B C D E F G H I J K L }
+
+impl<F: FnOnce(), const N: usize> RunAll for [F; N] {
+    fn run_all(self) -> Result<(), Box<dyn Any + Send>> {
+        let mut first_panic = None;
+        for f in self {
+            run_one(f, &mut first_panic);
+        }
+        first_panic.map_or(Ok(()), Err)
+    }
+}
+
+impl<F: FnOnce()> RunAll for Vec<F> {
+    fn run_all(self) -> Result<(), Box<dyn Any + Send>> {
+        let mut first_panic = None;
+        for f in self {
+            run_one(f, &mut first_panic);
+        }
+        first_panic.map_or(Ok(()), Err)
+    }
+}
+
+/// Calls every closure in a group when dropped, even if some of them panic.
+///
+/// Once all closures have been called, the first panic is re-raised. If the thread was already
+/// unwinding it is dropped instead, as re-raising it would abort the process.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::GuardGroup;
+/// use std::cell::RefCell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// let log = RefCell::new(Vec::new());
+/// let r = catch_unwind(AssertUnwindSafe(|| {
+///     let _guards = GuardGroup::new((
+///         || log.borrow_mut().push("a"),
+///         || panic!("b failed"),
+///         || log.borrow_mut().push("c"),
+///     ));
+/// }));
+/// assert_eq!(*r.unwrap_err().downcast::<&str>().unwrap(), "b failed");
+/// assert_eq!(*log.borrow(), ["a", "c"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GuardGroup<T: RunAll>(ManuallyDrop<T>, Site);
+
+impl<T: RunAll> GuardGroup<T> {
+    /// Creates a new `GuardGroup` from a group of closures.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(group: T) -> Self {
+        Self(ManuallyDrop::new(group), Site::caller::<Self>())
+    }
+
+    /// Unwraps the group without calling any of the closures.
+    pub fn into_inner(self) -> T {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.1);
+            ptr::read(&*this.0)
+        }
+    }
+}
+
+impl<T: RunAll + Default> Default for GuardGroup<T> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: RunAll> Drop for GuardGroup<T> {
+    fn drop(&mut self) {
+        let group: T = unsafe { ptr::read(&*self.0) };
+        if let Err(payload) = group.run_all() {
+            if !std::thread::panicking() {
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::RefCell;
+
+    #[test]
+    /// Every closure must be called and deallocated, with the first panic reported.
+    fn runs_every_closure() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+
+        let group: Vec<_> = (0..4)
+            .map(|i| {
+                let (log, token) = (&log, check.token());
+                move || {
+                    log.borrow_mut().push(i);
+                    drop(token);
+                    if i % 2 == 1 {
+                        panic!("{}", i);
+                    }
+                }
+            })
+            .collect();
+        let payload = group.run_all().unwrap_err();
+        assert_eq!(*payload.downcast::<String>().unwrap(), "1");
+        assert_eq!(*log.borrow(), [0, 1, 2, 3]);
+        assert!(check.all_dropped());
+
+        let token = check.token();
+        drop(GuardGroup::new([move || drop(token)]).into_inner());
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,106 @@
+//! Type-erased `FnOnce()` closures stored inline, without allocating.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ptr;
+
+/// `S` bytes of storage, aligned for anything short of SIMD types.
+#[repr(C, align(16))]
+struct Storage<const S: usize>([MaybeUninit<u8>; S]);
+
+/// Compile-time check that `F` fits in `Storage<S>`.
+struct Fits<F, const S: usize>(PhantomData<F>);
+
+impl<F, const S: usize> Fits<F, S> {
+    const OK: () = assert!(
+        mem::size_of::<F>() <= S && mem::align_of::<F>() <= mem::align_of::<Storage<S>>(),
+        "closure too large for inline storage"
+    );
+}
+
+/// Marks an [`InlineFnOnce`] that may hold any closure, so is neither `Send` nor `Sync`.
+pub(crate) type Local = *const ();
+
+/// Marks an [`InlineFnOnce`] that only holds `Send` closures, so is `Send` but not `Sync`.
+pub(crate) type SendOnly = Cell<()>;
+
+/// A type-erased `FnOnce() + 'a`, stored in `S` inline bytes.
+///
+/// Dropping it drops the closure without calling it. `M` is [`Local`] or [`SendOnly`], and
+/// decides whether the type is `Send`.
+pub(crate) struct InlineFnOnce<'a, const S: usize, M = Local> {
+    storage: Storage<S>,
+    call: unsafe fn(*mut u8),
+    drop: unsafe fn(*mut u8),
+    _marker: PhantomData<(&'a (), M)>,
+}
+
+unsafe fn call<F: FnOnce()>(p: *mut u8) {
+    ptr::read(p as *mut F)()
+}
+
+unsafe fn drop<F>(p: *mut u8) {
+    ptr::drop_in_place(p as *mut F)
+}
+
+impl<'a, const S: usize> InlineFnOnce<'a, S> {
+    /// Stores `f` inline.
+    ///
+    /// Fails to compile if `F` doesn't fit in `S` bytes.
+    pub(crate) fn new<F: FnOnce() + 'a>(f: F) -> Self {
+        unsafe { Self::new_unchecked(f) }
+    }
+}
+
+impl<'a, const S: usize> InlineFnOnce<'a, S, SendOnly> {
+    /// Stores `f` inline, keeping the type `Send`.
+    ///
+    /// Fails to compile if `F` doesn't fit in `S` bytes.
+    pub(crate) fn new_send<F: FnOnce() + Send + 'a>(f: F) -> Self {
+        unsafe { Self::new_unchecked(f) }
+    }
+}
+
+impl<'a, const S: usize, M> InlineFnOnce<'a, S, M> {
+    /// Stores `f` inline.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be `Send` if `M` is.
+    unsafe fn new_unchecked<F: FnOnce() + 'a>(f: F) -> Self {
+        let () = Fits::<F, S>::OK;
+
+        let mut storage = Storage([MaybeUninit::uninit(); S]);
+        unsafe { ptr::write(storage.0.as_mut_ptr() as *mut F, f) };
+        Self {
+            storage,
+            call: call::<F>,
+            drop: drop::<F>,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Calls the closure.
+    pub(crate) fn call(self) {
+        let mut this = ManuallyDrop::new(self);
+        unsafe { (this.call)(this.storage.0.as_mut_ptr() as *mut u8) }
+    }
+
+    /// Forgets whether the closure is `Send`.
+    pub(crate) fn into_local(self) -> InlineFnOnce<'a, S> {
+        let this = ManuallyDrop::new(self);
+        InlineFnOnce {
+            storage: unsafe { ptr::read(&this.storage) },
+            call: this.call,
+            drop: this.drop,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<const S: usize, M> Drop for InlineFnOnce<'_, S, M> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.storage.0.as_mut_ptr() as *mut u8) }
+    }
+}
@@ -0,0 +1,202 @@
+//! Detecting guards that were leaked rather than dropped.
+
+// `leak-detect` needs a newer Rust than the rest of the crate, as documented.
+#![allow(clippy::incompatible_msrv)]
+
+use core::fmt;
+use core::panic::Location;
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+
+static REGISTRY: Mutex<BTreeMap<u64, LiveGuard>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn registry() -> MutexGuard<'static, BTreeMap<u64, LiveGuard>> {
+    // Nothing panics while the lock is held, but keep tracking guards if it somehow did.
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn register(type_name: &'static str, location: &'static Location<'static>) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let guard = LiveGuard {
+        type_name,
+        location,
+        backtrace: Arc::new(Backtrace::capture()),
+    };
+    registry().insert(id, guard);
+    id
+}
+
+pub(crate) fn unregister(id: u64) {
+    let guard = registry().remove(&id);
+    drop(guard);
+}
+
+/// A guard that has been created, but not yet dropped or consumed.
+///
+/// Returned by [`live_guards`].
+#[derive(Clone)]
+pub struct LiveGuard {
+    type_name: &'static str,
+    location: &'static Location<'static>,
+    backtrace: Arc<Backtrace>,
+}
+
+impl LiveGuard {
+    /// Returns the type of the guard.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns where the guard was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns a backtrace of where the guard was created.
+    ///
+    /// Only captured if enabled with the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment
+    /// variables, as for [`Backtrace::capture`].
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Debug for LiveGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LiveGuard")
+            .field("type_name", &self.type_name)
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+impl fmt::Display for LiveGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} created at {}", self.type_name, self.location)
+    }
+}
+
+/// Returns every guard that is currently live, oldest first.
+///
+/// Guards that were forgotten with `mem::forget`, or that are stuck in a reference cycle, stay
+/// live forever.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::OnDrop;
+/// let guard = OnDrop::new(|| ());
+/// std::mem::forget(guard);
+///
+/// let leaked = ondrop::live_guards();
+/// assert!(leaked.iter().any(|g| g.location().file() == file!()));
+/// ```
+pub fn live_guards() -> Vec<LiveGuard> {
+    registry().values().cloned().collect()
+}
+
+/// Prints every live guard to stderr, returning how many there were.
+pub fn report_live_guards() -> usize {
+    let guards = live_guards();
+    for guard in &guards {
+        eprintln!("ondrop: live guard: {}", guard);
+        if let std::backtrace::BacktraceStatus::Captured = guard.backtrace().status() {
+            eprintln!("{}", guard.backtrace());
+        }
+    }
+    guards.len()
+}
+
+/// Arranges for [`report_live_guards`] to be called when the process exits.
+///
+/// Only the first call has any effect. The report is made from an `atexit` handler, so it
+/// covers guards leaked by `main` as well as by other threads.
+pub fn report_live_guards_at_exit() {
+    extern "C" {
+        fn atexit(f: extern "C" fn()) -> core::ffi::c_int;
+    }
+
+    extern "C" fn report() {
+        // Unwinding out of an atexit handler is undefined behaviour.
+        let _ = std::panic::catch_unwind(report_live_guards);
+    }
+
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        atexit(report);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{BoxOnDrop, ExitStack, InlineOnDrop, OnDrop, SendOnDrop};
+    use std::cell::Cell;
+
+    fn is_live(line: u32) -> bool {
+        live_guards()
+            .iter()
+            .any(|g| g.location().file() == file!() && g.location().line() == line)
+    }
+
+    #[test]
+    /// Guards must be live from creation until dropped or consumed, including clones.
+    fn tracks_live_guards() {
+        let (guard, line) = (OnDrop::new(|| ()), line!());
+        assert!(is_live(line));
+        drop(guard);
+        assert!(!is_live(line));
+
+        let (guard, line) = (OnDrop::new(|| ()), line!());
+        let clone = guard.clone();
+        guard.run();
+        assert!(is_live(line));
+        core::mem::forget(clone);
+        assert!(is_live(line));
+    }
+
+    #[test]
+    /// Erased guards must be registered where they were created, including when converted
+    /// from an `OnDrop`.
+    fn erased_guards_keep_site() {
+        let (guard, line) = (BoxOnDrop::new(|| ()), line!());
+        assert!(is_live(line));
+        drop(guard);
+
+        let (guard, line) = (OnDrop::new(|| ()), line!());
+        let guard = BoxOnDrop::from(SendOnDrop::from(guard));
+        assert!(is_live(line));
+        drop(guard);
+        assert!(!is_live(line));
+
+        let (guard, line) = (OnDrop::new(|| ()), line!());
+        let guard: InlineOnDrop = guard.into();
+        assert!(is_live(line));
+        drop(guard);
+        assert!(!is_live(line));
+    }
+
+    #[test]
+    /// Guards the crate uses internally must not be reported as live.
+    fn ignores_internal_guards() {
+        let internal = Cell::new(true);
+        let mut stack = ExitStack::new();
+        stack.push(|| {
+            let live = live_guards();
+            internal.set(live.iter().any(|g| g.location().file() == "src/unwind.rs"))
+        });
+        drop(stack);
+        assert!(!internal.get());
+
+        crate::scope(|s| {
+            s.defer(|| ());
+            let live = live_guards();
+            internal.set(live.iter().any(|g| g.location().file() == "src/scope.rs"))
+        });
+        assert!(!internal.get());
+    }
+}
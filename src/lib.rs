@@ -1,11 +1,159 @@
 //! Do something on drop.
+//!
+//! # Features
+//!
+//! The crate is `no_std`; [`OnDrop`](struct@OnDrop) itself only needs `core`. Everything else is
+//! gated behind cargo features:
+//!
+//! * `alloc`: guards that allocate, such as `ExitStack`.
+//! * `std` (default, implies `alloc`): guards that need to know whether the thread is
+//!   panicking, such as `OnUnwind` and `OnSuccess`, or that catch panics, such as
+//!   `PolicyOnDrop` and `GuardGroup`.
+//! * `track-caller`: guards record where they were created, for [`OnDrop::location`] and
+//!   `Debug` output.
+//! * `leak-detect` (implies `std` and `track-caller`): every live guard is recorded in a global
+//!   registry, so that guards leaked with `mem::forget` or reference cycles can be found with
+//!   `live_guards`, or reported at exit with `report_live_guards_at_exit`.
+//! * `derive`: `#[on_drop]`, which gives a struct a `Drop` impl that passes its fields by value
+//!   to a method, and `#[cleanup]`, which runs a cleanup on every exit from a function.
+//!
+//! # Minimum supported Rust version
+//!
+//! Rust 1.59, for const generic defaults. `leak-detect` needs Rust 1.66.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::fmt;
 use core::mem::ManuallyDrop;
+use core::panic::Location;
 use core::ptr;
 
+mod site;
+use site::Site;
+
+mod unwind;
+
+#[cfg(feature = "leak-detect")]
+mod leak;
+#[cfg(feature = "leak-detect")]
+pub use leak::{live_guards, report_live_guards, report_live_guards_at_exit, LiveGuard};
+
+mod macros;
+
+#[cfg(feature = "std")]
+mod outcome;
+#[cfg(feature = "std")]
+pub use outcome::{OnSuccess, OnUnwind};
+
+#[cfg(feature = "std")]
+mod panic_policy;
+#[cfg(feature = "std")]
+pub use panic_policy::{PanicPolicy, PanicStore, PolicyOnDrop};
+
+#[cfg(feature = "std")]
+mod group;
+#[cfg(feature = "std")]
+pub use group::{GuardGroup, RunAll};
+
+mod reason;
+pub use reason::{DropReason, OnDropReason};
+
+mod drop_with;
+pub use drop_with::DropWith;
+
+mod try_on_drop;
+pub use try_on_drop::TryOnDrop;
+
+mod by_value;
+pub use by_value::{DropByValue, Owned};
+#[cfg(feature = "derive")]
+pub use ondrop_derive::{cleanup, on_drop};
+
+mod bomb;
+pub use bomb::{Detonation, DropBomb, MustConsume};
+
+mod never_drop;
+pub use never_drop::NeverDrop;
+
+#[cfg(feature = "alloc")]
+mod exit_stack;
+#[cfg(feature = "alloc")]
+pub use exit_stack::{ExitStack, ExitStackWith};
+
+#[cfg(feature = "alloc")]
+mod transaction;
+#[cfg(feature = "alloc")]
+pub use transaction::{Savepoint, Transaction};
+
+#[cfg(feature = "alloc")]
+mod tx_vec;
+#[cfg(feature = "alloc")]
+pub use tx_vec::TxVec;
+
+#[cfg(feature = "std")]
+mod tx_map;
+#[cfg(feature = "std")]
+pub use tx_map::TxMap;
+
+#[cfg(feature = "alloc")]
+mod scope;
+#[cfg(feature = "alloc")]
+pub use scope::{scope, try_scope, Scope, TryScope};
+
+mod inline;
+
+mod erased;
+#[cfg(feature = "alloc")]
+pub use erased::{BoxOnDrop, SendOnDrop};
+pub use erased::{InlineOnDrop, InlineSendOnDrop};
+
+mod array_exit_stack;
+pub use array_exit_stack::{ArrayExitStack, CapacityError};
+
+/// A cleanup action, run by value.
+///
+/// Implemented for every `FnOnce()` closure. Implement it for your own types to give a cleanup a
+/// name that shows up in type signatures, and that can derive traits meaningfully.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::{Cleanup, OnDrop};
+/// use std::sync::mpsc::{channel, Sender};
+///
+/// #[derive(Debug, Clone)]
+/// struct Release(Sender<u32>, u32);
+///
+/// impl Cleanup for Release {
+///     fn cleanup(self) {
+///         self.0.send(self.1).unwrap();
+///     }
+/// }
+///
+/// let (tx, rx) = channel();
+/// let guard: OnDrop<Release> = OnDrop::new(Release(tx, 42));
+/// drop(guard);
+/// assert_eq!(rx.recv().unwrap(), 42);
+/// ```
+pub trait Cleanup {
+    /// Performs the cleanup.
+    fn cleanup(self);
+}
+
+impl<F: FnOnce()> Cleanup for F {
+    #[inline(always)]
+    fn cleanup(self) {
+        self()
+    }
+}
+
 /// Calls the wrapped closure when dropped.
 ///
-/// That's it.
+/// That's it. More generally, `OnDrop` can hold any [`Cleanup`], of which closures are the
+/// common case.
 ///
 /// # Examples
 ///
@@ -22,13 +170,22 @@ use core::ptr;
 ///
 /// assert_eq!(drops, 1);
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
-pub struct OnDrop<F: FnOnce()>(ManuallyDrop<F>);
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OnDrop<F: Cleanup> {
+    f: ManuallyDrop<F>,
+    armed: bool,
+    site: Site,
+}
 
-impl<F: FnOnce()> OnDrop<F> {
+impl<F: Cleanup> OnDrop<F> {
     /// Creates a new `OnDrop` from a closure.
+    #[cfg_attr(feature = "track-caller", track_caller)]
     pub fn new(f: F) -> Self {
-        Self(ManuallyDrop::new(f))
+        Self {
+            f: ManuallyDrop::new(f),
+            armed: true,
+            site: Site::caller::<Self>(),
+        }
     }
 
     /// Unwraps the closure without calling it.
@@ -40,17 +197,116 @@ impl<F: FnOnce()> OnDrop<F> {
     /// dropper.into_inner(); // no panic
     /// ```
     pub fn into_inner(self) -> F {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.site);
+            ptr::read(&*this.f)
+        }
+    }
+
+    /// Splits the guard into its closure, armed state and creation site, without calling the
+    /// closure.
+    pub(crate) fn into_parts(self) -> (F, bool, Site) {
         let this = ManuallyDrop::new(self);
-        unsafe { ptr::read(&*this.0) }
+        unsafe { (ptr::read(&*this.f), this.armed, ptr::read(&this.site)) }
+    }
+
+    /// The inverse of [`into_parts`](Self::into_parts).
+    #[cfg(feature = "alloc")]
+    pub(crate) fn from_parts(f: F, armed: bool, site: Site) -> Self {
+        Self {
+            f: ManuallyDrop::new(f),
+            armed,
+            site,
+        }
+    }
+
+    /// Calls the closure now, consuming the guard.
+    ///
+    /// The closure is called even if the guard has been disarmed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::OnDrop;
+    /// let mut ran = false;
+    /// let mut dropper = OnDrop::new(|| ran = true);
+    /// dropper.disarm();
+    /// dropper.run();
+    /// assert!(ran);
+    /// ```
+    pub fn run(self) {
+        self.into_inner().cleanup()
+    }
+
+    /// Disarms the guard, so that dropping it will not call the closure.
+    ///
+    /// Unlike [`into_inner`](Self::into_inner) this works in-place, which is handy when the
+    /// guard lives in a struct field.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::OnDrop;
+    /// let mut dropper = OnDrop::new(|| panic!());
+    /// dropper.disarm();
+    /// assert!(!dropper.is_armed());
+    /// drop(dropper); // no panic
+    /// ```
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+
+    /// Re-arms a disarmed guard, so that dropping it calls the closure again.
+    pub fn arm(&mut self) {
+        self.armed = true;
+    }
+
+    /// Returns `true` if dropping the guard will call the closure.
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Returns where the guard was created.
+    ///
+    /// Always `None` unless the `track-caller` feature is enabled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::OnDrop;
+    /// let (dropper, line) = (OnDrop::new(|| ()), line!());
+    /// if let Some(location) = dropper.location() {
+    ///     assert_eq!(location.line(), line);
+    /// }
+    /// ```
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.site.location()
+    }
+}
+
+impl<F: Cleanup + Default> Default for OnDrop<F> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(F::default())
+    }
+}
+
+impl<F: Cleanup> fmt::Debug for OnDrop<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnDrop")
+            .field("closure", &core::any::type_name::<F>())
+            .field("armed", &self.armed)
+            .field("site", &self.site)
+            .finish()
     }
 }
 
-impl<F: FnOnce()> Drop for OnDrop<F> {
+impl<F: Cleanup> Drop for OnDrop<F> {
     #[inline(always)]
     fn drop(&mut self) {
         unsafe {
-            let f: F = core::ptr::read(&*self.0);
-            f()
+            let f: F = core::ptr::read(&*self.f);
+            if self.armed {
+                f.cleanup()
+            }
         }
     }
 }
@@ -60,7 +316,7 @@ mod tests {
     use super::*;
 
     use std::cell::Cell;
-    use dropcheck::{DropCheck, DropToken};
+    use dropcheck::DropCheck;
 
     #[test]
     /// Make sure the closure is deallocated once and only once.
@@ -68,13 +324,13 @@ mod tests {
         let check = DropCheck::new();
         let (token, state) = check.pair();
 
-        let mut dst = Cell::new(None);
+        let dst = Cell::new(None);
         let ondrop = OnDrop::new(|| {
             dst.set(Some(token));
         });
 
         assert!(state.is_not_dropped());
-        ondrop.into_inner();
+        drop(ondrop.into_inner());
         assert!(state.is_dropped());
         assert!(dst.take().is_none());
 
@@ -88,4 +344,46 @@ mod tests {
         assert!(state.is_not_dropped());
         assert!(dst.take().is_some());
     }
+
+    #[test]
+    fn debug_shows_closure_type_and_armed_state() {
+        let mut ondrop = OnDrop::new(drops_closure_once);
+        ondrop.disarm();
+        let debug = format!("{:?}", ondrop);
+        assert!(debug.contains("drops_closure_once"), "{}", debug);
+        assert!(debug.contains("armed: false"), "{}", debug);
+        if cfg!(feature = "track-caller") {
+            assert!(debug.contains("src/lib.rs"), "{}", debug);
+        }
+    }
+
+    #[test]
+    /// A disarmed guard must still deallocate its closure, and a re-armed one must call it.
+    fn disarm_and_rearm() {
+        let check = DropCheck::new();
+        let (token, state) = check.pair();
+
+        let dst = Cell::new(None);
+        let mut ondrop = OnDrop::new(|| {
+            dst.set(Some(token));
+        });
+
+        ondrop.disarm();
+        assert!(!ondrop.is_armed());
+        drop(ondrop);
+        assert!(state.is_dropped());
+        assert!(dst.take().is_none());
+
+        let (token, state) = check.pair();
+        let mut ondrop = OnDrop::new(|| {
+            dst.set(Some(token));
+        });
+
+        ondrop.disarm();
+        ondrop.arm();
+        assert!(ondrop.is_armed());
+        drop(ondrop);
+        assert!(state.is_not_dropped());
+        assert!(dst.take().is_some());
+    }
 }
@@ -0,0 +1,132 @@
+//! `defer!`-style macros.
+
+/// Runs the given statements when the enclosing scope is left.
+///
+/// Expands to a hidden [`OnDrop`](struct@crate::OnDrop) binding, so the guard can't accidentally be
+/// bound to `_` and dropped immediately. Multiple `defer!`s in the same scope run in reverse
+/// order, like any other locals.
+///
+/// By default the statements borrow what they use; prefix them with `move` to move captured
+/// values into the guard instead.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::defer;
+/// use std::cell::RefCell;
+///
+/// let log = RefCell::new(Vec::new());
+/// {
+///     defer! {
+///         log.borrow_mut().push("first");
+///     }
+///     defer! {
+///         log.borrow_mut().push("second");
+///     }
+///     log.borrow_mut().push("body");
+/// }
+/// assert_eq!(*log.borrow(), ["body", "second", "first"]);
+/// ```
+///
+/// Moving a value into the guard:
+///
+/// ```
+/// # use ondrop::defer;
+/// use std::sync::mpsc::channel;
+///
+/// let (tx, rx) = channel();
+/// {
+///     defer!(move tx.send("done").unwrap());
+/// }
+/// assert_eq!(rx.recv().unwrap(), "done");
+/// ```
+#[macro_export]
+macro_rules! defer {
+    (move $($body:tt)*) => {
+        let _ondrop_guard = $crate::OnDrop::new(move || { $($body)* });
+    };
+    ($($body:tt)*) => {
+        let _ondrop_guard = $crate::OnDrop::new(|| { $($body)* });
+    };
+}
+
+/// Runs the given statements when the enclosing scope is left by unwinding.
+///
+/// Like [`defer!`], but expands to an [`OnUnwind`](crate::OnUnwind) guard.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::defer_on_unwind;
+/// use std::cell::Cell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// let rolled_back = Cell::new(false);
+/// let r = catch_unwind(AssertUnwindSafe(|| {
+///     defer_on_unwind! {
+///         rolled_back.set(true);
+///     }
+///     panic!();
+/// }));
+/// assert!(r.is_err());
+/// assert!(rolled_back.get());
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! defer_on_unwind {
+    (move $($body:tt)*) => {
+        let _ondrop_guard = $crate::OnUnwind::new(move || { $($body)* });
+    };
+    ($($body:tt)*) => {
+        let _ondrop_guard = $crate::OnUnwind::new(|| { $($body)* });
+    };
+}
+
+/// Runs the given statements when the enclosing scope is left normally.
+///
+/// Like [`defer!`], but expands to an [`OnSuccess`](crate::OnSuccess) guard.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::defer_on_success;
+/// use std::cell::Cell;
+///
+/// let committed = Cell::new(false);
+/// {
+///     defer_on_success! {
+///         committed.set(true);
+///     }
+/// }
+/// assert!(committed.get());
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! defer_on_success {
+    (move $($body:tt)*) => {
+        let _ondrop_guard = $crate::OnSuccess::new(move || { $($body)* });
+    };
+    ($($body:tt)*) => {
+        let _ondrop_guard = $crate::OnSuccess::new(|| { $($body)* });
+    };
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::cell::RefCell;
+
+    #[test]
+    /// Guards declared with the macros must run in reverse order at the end of the scope.
+    fn runs_in_reverse_order() {
+        let log = RefCell::new(Vec::new());
+        {
+            defer!(log.borrow_mut().push(1));
+            defer_on_success!(log.borrow_mut().push(2));
+            defer_on_unwind!(log.borrow_mut().push(3));
+            let (log, n) = (&log, 4);
+            defer!(move log.borrow_mut().push(n));
+            assert!(log.borrow().is_empty());
+        }
+        assert_eq!(*log.borrow(), [4, 2, 1]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Values that must never be implicitly dropped, checked at link time.
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+/// Wraps a value that must be consumed with [`into_inner`](Self::into_inner), never dropped.
+///
+/// In optimized builds the `Drop` impl calls a function that doesn't exist. If the optimizer
+/// can prove that every `NeverDrop` is consumed, the call is removed and the program links as
+/// normal; otherwise linking fails, with an error naming this type. In debug builds, where
+/// nothing is optimized out, dropping a `NeverDrop` panics instead, unless the thread is
+/// already unwinding.
+///
+/// This is a cheap way to get linear types, but only as good as the optimizer: any call that
+/// could panic while a `NeverDrop` is alive adds an unwinding path that drops it. It works
+/// best with `panic = "abort"`, or with values whose lifetime doesn't span such calls.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::NeverDrop;
+/// struct Transaction(u32);
+///
+/// fn commit(tx: NeverDrop<Transaction>) -> u32 {
+///     tx.into_inner().0
+/// }
+///
+/// let tx = NeverDrop::new(Transaction(7));
+/// let id = commit(tx);
+/// assert_eq!(id, 7);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NeverDrop<T>(ManuallyDrop<T>);
+
+impl<T> NeverDrop<T> {
+    /// Wraps a value.
+    pub fn new(value: T) -> Self {
+        Self(ManuallyDrop::new(value))
+    }
+
+    /// Consumes the wrapper, returning the value.
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read(&*this.0) }
+    }
+}
+
+impl<T> Deref for NeverDrop<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for NeverDrop<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> Drop for NeverDrop<T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        #[cfg(not(debug_assertions))]
+        {
+            extern "C" {
+                #[link_name = "\n\nondrop::NeverDrop was dropped on a path the optimizer could not rule out; consume it with into_inner instead\n\n"]
+                fn never_drop_was_dropped() -> !;
+            }
+            unsafe { never_drop_was_dropped() }
+        }
+        #[cfg(debug_assertions)]
+        {
+            unsafe { ManuallyDrop::drop(&mut self.0) };
+            #[cfg(feature = "std")]
+            {
+                if std::thread::panicking() {
+                    return;
+                }
+            }
+            panic!("NeverDrop<{}> was dropped", core::any::type_name::<T>())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+
+    #[test]
+    /// Make sure the value is deallocated once and only once.
+    fn into_inner_returns_value() {
+        let check = DropCheck::new();
+        let token = NeverDrop::new(check.token());
+        drop(token.into_inner());
+        assert!(check.all_dropped());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "NeverDrop<u8> was dropped")]
+    fn panics_when_dropped_in_debug_builds() {
+        drop(NeverDrop::new(0u8));
+    }
+
+    #[cfg(all(debug_assertions, feature = "std"))]
+    #[test]
+    /// Dropping a `NeverDrop` while unwinding must not panic again, which would abort.
+    fn does_not_panic_while_unwinding() {
+        let check = DropCheck::new();
+        let r = std::panic::catch_unwind(|| {
+            let _token = NeverDrop::new(check.token());
+            panic!("boom");
+        });
+        assert_eq!(*r.unwrap_err().downcast::<&str>().unwrap(), "boom");
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,175 @@
+//! Guards that only run for one outcome of the scope.
+
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::site::Site;
+
+/// Calls the wrapped closure when dropped, but only if the thread is unwinding.
+///
+/// Useful for rolling back a partially completed operation when a panic tears through it.
+/// If the guard is dropped normally the closure is dropped without being called.
+///
+/// Detecting unwinding relies on `std::thread::panicking()`. Without `std` there is no way to tell
+/// the two cases apart; `no_std` code should use [`OnDrop`](struct@crate::OnDrop) and disarm it
+/// explicitly with `into_inner` on the success path instead.
+///
+/// `panicking()` describes the whole thread, not the guard's own scope. A guard created inside a
+/// destructor that runs during unwinding sees the thread as unwinding even if that scope is left
+/// normally, so it calls its closure there too.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::OnUnwind;
+/// use std::cell::Cell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// let rolled_back = Cell::new(false);
+/// {
+///     let _guard = OnUnwind::new(|| rolled_back.set(true));
+/// }
+/// assert!(!rolled_back.get());
+///
+/// let r = catch_unwind(AssertUnwindSafe(|| {
+///     let _guard = OnUnwind::new(|| rolled_back.set(true));
+///     panic!();
+/// }));
+/// assert!(r.is_err());
+/// assert!(rolled_back.get());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OnUnwind<F: FnOnce()>(ManuallyDrop<F>, Site);
+
+impl<F: FnOnce()> OnUnwind<F> {
+    /// Creates a new `OnUnwind` from a closure.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(f: F) -> Self {
+        Self(ManuallyDrop::new(f), Site::caller::<Self>())
+    }
+
+    /// Unwraps the closure without calling it.
+    pub fn into_inner(self) -> F {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.1);
+            ptr::read(&*this.0)
+        }
+    }
+}
+
+impl<F: FnOnce() + Default> Default for OnUnwind<F> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(F::default())
+    }
+}
+
+impl<F: FnOnce()> Drop for OnUnwind<F> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let f: F = unsafe { ptr::read(&*self.0) };
+        if std::thread::panicking() {
+            f()
+        }
+    }
+}
+
+/// Calls the wrapped closure when dropped, but only if the thread is *not* unwinding.
+///
+/// The mirror image of [`OnUnwind`]: handy for committing work once the scope has been left
+/// normally, whether by falling off the end, `return` or `?`.
+///
+/// Like `OnUnwind` this needs `std::thread::panicking()`; in `no_std` code call the commit
+/// step explicitly at the end of the scope.
+///
+/// As with `OnUnwind`, `panicking()` describes the whole thread: a guard created inside a
+/// destructor that runs during unwinding never calls its closure, even if that scope is left
+/// normally.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::OnSuccess;
+/// use std::cell::Cell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// let committed = Cell::new(false);
+/// let r = catch_unwind(AssertUnwindSafe(|| {
+///     let _guard = OnSuccess::new(|| committed.set(true));
+///     panic!();
+/// }));
+/// assert!(r.is_err());
+/// assert!(!committed.get());
+///
+/// {
+///     let _guard = OnSuccess::new(|| committed.set(true));
+/// }
+/// assert!(committed.get());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OnSuccess<F: FnOnce()>(ManuallyDrop<F>, Site);
+
+impl<F: FnOnce()> OnSuccess<F> {
+    /// Creates a new `OnSuccess` from a closure.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(f: F) -> Self {
+        Self(ManuallyDrop::new(f), Site::caller::<Self>())
+    }
+
+    /// Unwraps the closure without calling it.
+    pub fn into_inner(self) -> F {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.1);
+            ptr::read(&*this.0)
+        }
+    }
+}
+
+impl<F: FnOnce() + Default> Default for OnSuccess<F> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(F::default())
+    }
+}
+
+impl<F: FnOnce()> Drop for OnSuccess<F> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let f: F = unsafe { ptr::read(&*self.0) };
+        if !std::thread::panicking() {
+            f()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    /// The closure must be deallocated whether or not it was called.
+    fn drops_closure_on_both_outcomes() {
+        let check = DropCheck::new();
+
+        let (token, state) = check.pair();
+        drop(OnUnwind::new(move || drop(token)));
+        assert!(state.is_dropped());
+
+        let (token, state) = check.pair();
+        drop(OnSuccess::new(move || drop(token)));
+        assert!(state.is_dropped());
+
+        let (token, state) = check.pair();
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = OnSuccess::new(move || drop(token));
+            panic!();
+        }));
+        assert!(r.is_err());
+        assert!(state.is_dropped());
+    }
+}
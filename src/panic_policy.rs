@@ -0,0 +1,267 @@
+//! Choosing what happens when a cleanup closure panics.
+
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::panic::Location;
+use core::ptr;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::{Cleanup, OnDrop};
+
+/// What to do when a cleanup closure panics.
+///
+/// A panic raised by a plain [`OnDrop`](struct@OnDrop) propagates like any other; if the thread was
+/// already unwinding that aborts the process with little indication of which cleanup was at fault.
+/// A [`PolicyOnDrop`] lets you decide per guard.
+#[derive(Debug, Clone)]
+pub enum PanicPolicy {
+    /// Let the panic propagate, exactly like [`OnDrop`](struct@OnDrop). The default.
+    Propagate,
+
+    /// Catch the panic and print it, along with where the guard was created, to stderr.
+    Log,
+
+    /// Catch the panic and store its payload in a [`PanicStore`] for later inspection.
+    Store(PanicStore),
+
+    /// Print the panic and where the guard was created to stderr, then abort the process.
+    Abort,
+}
+
+impl Default for PanicPolicy {
+    fn default() -> Self {
+        PanicPolicy::Propagate
+    }
+}
+
+impl PanicPolicy {
+    /// Calls `f`, handling any panic according to this policy.
+    ///
+    /// `location` is where the guard calling `f` was created, and is only used for reporting.
+    pub(crate) fn call(&self, f: impl FnOnce(), location: &Location) {
+        if let PanicPolicy::Propagate = self {
+            return f();
+        }
+
+        let unwinding = std::thread::panicking();
+        // The closure is consumed either way, so it can't be observed in a broken state.
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            let report = || {
+                eprintln!(
+                    "ondrop: cleanup closure created at {} panicked{}: {}",
+                    location,
+                    if unwinding { " while unwinding" } else { "" },
+                    payload_message(&*payload)
+                )
+            };
+            match self {
+                PanicPolicy::Propagate => unreachable!(),
+                PanicPolicy::Log => report(),
+                PanicPolicy::Store(store) => store.push(payload),
+                PanicPolicy::Abort => {
+                    report();
+                    std::process::abort()
+                }
+            }
+        }
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Shared storage for panics caught by [`PanicPolicy::Store`].
+///
+/// Cloning a `PanicStore` gives another handle to the same storage.
+#[derive(Clone, Default)]
+pub struct PanicStore(Arc<Mutex<Vec<Box<dyn Any + Send>>>>);
+
+impl PanicStore {
+    /// Creates a new, empty, `PanicStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes and returns all stored panic payloads, oldest first.
+    pub fn take_all(&self) -> Vec<Box<dyn Any + Send>> {
+        core::mem::take(&mut *self.lock())
+    }
+
+    /// Returns the number of stored panic payloads.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if no panics have been stored.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn push(&self, payload: Box<dyn Any + Send>) {
+        self.lock().push(payload)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Box<dyn Any + Send>>> {
+        // Nothing panics while the lock is held, but don't lose payloads if it somehow did.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for PanicStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PanicStore")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Calls the wrapped closure when dropped, handling panics according to a [`PanicPolicy`].
+///
+/// # Examples
+///
+/// Report a failing cleanup instead of aborting mid-unwind:
+///
+/// ```
+/// # use ondrop::{PanicPolicy, PanicStore, PolicyOnDrop};
+/// use std::panic::catch_unwind;
+///
+/// let store = PanicStore::new();
+/// let r = catch_unwind(|| {
+///     let policy = PanicPolicy::Store(store.clone());
+///     let _guard = PolicyOnDrop::new(|| panic!("cleanup failed"), policy);
+///     panic!("body failed");
+/// });
+/// assert_eq!(*r.unwrap_err().downcast::<&str>().unwrap(), "body failed");
+///
+/// let stored = store.take_all();
+/// assert_eq!(*stored[0].downcast_ref::<&str>().unwrap(), "cleanup failed");
+/// ```
+pub struct PolicyOnDrop<F: Cleanup> {
+    guard: ManuallyDrop<OnDrop<F>>,
+    policy: PanicPolicy,
+    location: &'static Location<'static>,
+}
+
+impl<F: Cleanup> PolicyOnDrop<F> {
+    /// Creates a new `PolicyOnDrop` from a closure and a policy.
+    #[track_caller]
+    pub fn new(f: F, policy: PanicPolicy) -> Self {
+        OnDrop::new(f).with_panic_policy(policy)
+    }
+
+    /// Unwraps the closure without calling it.
+    pub fn into_inner(self) -> F {
+        self.into_on_drop().into_inner()
+    }
+
+    /// Unwraps the underlying [`OnDrop`](struct@OnDrop), discarding the policy.
+    pub fn into_on_drop(self) -> OnDrop<F> {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.policy);
+            ManuallyDrop::take(&mut this.guard)
+        }
+    }
+
+    /// Disarms the guard, so that dropping it will not call the closure.
+    pub fn disarm(&mut self) {
+        self.guard.disarm()
+    }
+
+    /// Re-arms a disarmed guard, so that dropping it calls the closure again.
+    pub fn arm(&mut self) {
+        self.guard.arm()
+    }
+
+    /// Returns `true` if dropping the guard will call the closure.
+    pub fn is_armed(&self) -> bool {
+        self.guard.is_armed()
+    }
+
+    /// Returns the policy used when the closure panics.
+    pub fn policy(&self) -> &PanicPolicy {
+        &self.policy
+    }
+
+    /// Returns where the guard was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl<F: Cleanup> fmt::Debug for PolicyOnDrop<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PolicyOnDrop")
+            .field("armed", &self.is_armed())
+            .field("policy", &self.policy)
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+impl<F: Cleanup> Drop for PolicyOnDrop<F> {
+    fn drop(&mut self) {
+        let guard = unsafe { ManuallyDrop::take(&mut self.guard) };
+        self.policy.call(|| drop(guard), self.location)
+    }
+}
+
+impl<F: Cleanup> OnDrop<F> {
+    /// Converts into a [`PolicyOnDrop`], handling panics in the closure according to `policy`.
+    ///
+    /// The guard's creation site is taken to be the caller of this method.
+    #[track_caller]
+    pub fn with_panic_policy(self, policy: PanicPolicy) -> PolicyOnDrop<F> {
+        PolicyOnDrop {
+            guard: ManuallyDrop::new(self),
+            policy,
+            location: Location::caller(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::panic::catch_unwind;
+
+    #[test]
+    /// Caught panics must not stop the closure from being deallocated, nor escape the guard.
+    fn stores_panic_and_drops_closure() {
+        let check = DropCheck::new();
+        let store = PanicStore::new();
+
+        let token = check.token();
+        drop(PolicyOnDrop::new(
+            move || {
+                let _token = token;
+                panic!("oops");
+            },
+            PanicPolicy::Store(store.clone()),
+        ));
+        assert!(check.all_dropped());
+        assert_eq!(store.len(), 1);
+
+        let mut guard = OnDrop::new(|| panic!()).with_panic_policy(PanicPolicy::Log);
+        guard.disarm();
+        drop(guard);
+
+        let r = catch_unwind(|| {
+            drop(OnDrop::new(|| panic!("propagated")).with_panic_policy(PanicPolicy::Propagate));
+        });
+        assert!(r.is_err());
+        assert_eq!(store.take_all().len(), 1);
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! Guards whose closure is told why it is being called.
+
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::site::Site;
+
+/// Why a cleanup is being called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// The guard went out of scope normally.
+    ///
+    /// Without the `std` feature there is no way to detect unwinding, so this is also used
+    /// while unwinding.
+    Scope,
+
+    /// The guard is being dropped because the thread is panicking.
+    ///
+    /// Never produced without the `std` feature. This comes from `std::thread::panicking()`, which
+    /// describes the whole thread: a guard created inside a destructor that runs during unwinding
+    /// is given `Unwinding` even if that scope is left normally.
+    Unwinding,
+
+    /// The guard was consumed with an explicit call, such as [`OnDropReason::run`].
+    ExplicitRun,
+
+    /// The guard was on an `ExitStack` that was explicitly closed.
+    StackClose,
+}
+
+impl DropReason {
+    /// The reason for a guard being dropped right now: `Unwinding` if the thread is panicking,
+    /// `Scope` otherwise.
+    pub(crate) fn dropped() -> Self {
+        #[cfg(feature = "std")]
+        {
+            if std::thread::panicking() {
+                return DropReason::Unwinding;
+            }
+        }
+        DropReason::Scope
+    }
+}
+
+/// Calls the wrapped closure when dropped, passing the [`DropReason`].
+///
+/// Lets one cleanup act differently depending on how it was triggered, rather than keeping
+/// several guards with shared flags.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::{DropReason, OnDropReason};
+/// let mut reasons = Vec::new();
+/// {
+///     let _guard = OnDropReason::new(|reason| reasons.push(reason));
+/// }
+/// assert_eq!(reasons, [DropReason::Scope]);
+/// ```
+///
+/// Calling the closure explicitly:
+///
+/// ```
+/// # use ondrop::{DropReason, OnDropReason};
+/// let mut reasons = Vec::new();
+/// let guard = OnDropReason::new(|reason| reasons.push(reason));
+/// guard.run();
+/// assert_eq!(reasons, [DropReason::ExplicitRun]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OnDropReason<F: FnOnce(DropReason)>(ManuallyDrop<F>, Site);
+
+impl<F: FnOnce(DropReason)> OnDropReason<F> {
+    /// Creates a new `OnDropReason` from a closure.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(f: F) -> Self {
+        Self(ManuallyDrop::new(f), Site::caller::<Self>())
+    }
+
+    /// Unwraps the closure without calling it.
+    pub fn into_inner(self) -> F {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.1);
+            ptr::read(&*this.0)
+        }
+    }
+
+    /// Calls the closure now with [`DropReason::ExplicitRun`], consuming the guard.
+    pub fn run(self) {
+        self.into_inner()(DropReason::ExplicitRun)
+    }
+}
+
+impl<F: FnOnce(DropReason) + Default> Default for OnDropReason<F> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(F::default())
+    }
+}
+
+impl<F: FnOnce(DropReason)> Drop for OnDropReason<F> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let f: F = unsafe { ptr::read(&*self.0) };
+        f(DropReason::dropped())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    /// The closure must be told it is being called because of a panic.
+    fn passes_unwinding_reason() {
+        let check = DropCheck::new();
+        let (token, state) = check.pair();
+        let reason = Cell::new(None);
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = OnDropReason::new(|r| {
+                reason.set(Some(r));
+                drop(token);
+            });
+            panic!();
+        }));
+        assert!(r.is_err());
+        assert_eq!(reason.get(), Some(DropReason::Unwinding));
+        assert!(state.is_dropped());
+    }
+}
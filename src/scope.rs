@@ -0,0 +1,264 @@
+//! Cleanups registered through a scope handle, rather than held in guard variables.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::unwind::unwind;
+
+/// Creates a scope in which cleanups can be registered with [`Scope::defer`].
+///
+/// The cleanups are called in reverse order once `f` returns or unwinds, in the spirit of
+/// `std::thread::scope`. They may borrow anything that outlives the call to `scope`, and as the
+/// handle is shared there is no guard variable holding the borrow: registering cleanups from
+/// loops, branches or helper functions just works.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// let log = &RefCell::new(Vec::new());
+/// let n = ondrop::scope(|s| {
+///     for i in 0..3 {
+///         s.defer(move || log.borrow_mut().push(i));
+///     }
+///     log.borrow_mut().push(99);
+///     log.borrow().len()
+/// });
+/// assert_eq!(n, 1);
+/// assert_eq!(*log.borrow(), [99, 2, 1, 0]);
+/// ```
+///
+/// Cleanups can't borrow locals of the scope itself, as those are gone by the time they run:
+///
+/// ```compile_fail
+/// ondrop::scope(|s| {
+///     let v = vec![1, 2, 3];
+///     s.defer(|| drop(v.len()));
+/// });
+/// ```
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: FnOnce(&Scope<'env>) -> T,
+{
+    let scope = Scope {
+        stack: RefCell::new(Vec::new()),
+        _env: PhantomData,
+    };
+    f(&scope)
+}
+
+/// A handle for registering cleanups in a [`scope`].
+pub struct Scope<'env> {
+    stack: RefCell<Vec<Box<dyn FnOnce() + 'env>>>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'env> Scope<'env> {
+    /// Registers a closure to be called when the scope ends.
+    pub fn defer(&self, f: impl FnOnce() + 'env) {
+        self.stack.borrow_mut().push(Box::new(f))
+    }
+}
+
+impl fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("len", &self.stack.borrow().len())
+            .finish()
+    }
+}
+
+impl Drop for Scope<'_> {
+    fn drop(&mut self) {
+        unwind(self.stack.get_mut(), Vec::pop, |_, f| f(), |_, f| f())
+    }
+}
+
+/// Creates a scope whose cleanups can depend on whether `f` succeeds.
+///
+/// Like [`scope`], but `f` returns a `Result`, and as well as unconditional cleanups registered
+/// with [`TryScope::defer`] it can register ones that only run on error or on success. All of
+/// them are called in reverse order of registration, once `f` returns or unwinds.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// let log = &RefCell::new(Vec::new());
+/// let r: Result<(), &str> = ondrop::try_scope(|s| {
+///     log.borrow_mut().push("create");
+///     s.on_err(move |e| log.borrow_mut().push(e.copied().unwrap_or("panic")));
+///     s.on_ok(move |_| log.borrow_mut().push("commit"));
+///     Err("rollback")
+/// });
+/// assert_eq!(r, Err("rollback"));
+/// assert_eq!(*log.borrow(), ["create", "rollback"]);
+/// ```
+pub fn try_scope<'env, F, T, E>(f: F) -> Result<T, E>
+where
+    F: FnOnce(&TryScope<'env, T, E>) -> Result<T, E>,
+{
+    let scope = TryScope {
+        stack: RefCell::new(Vec::new()),
+        _env: PhantomData,
+    };
+    let result = f(&scope);
+    let mut stack = scope.stack.take();
+    match &result {
+        Ok(value) => close(&mut stack, Outcome::Ok(value)),
+        Err(e) => close(&mut stack, Outcome::Err(e)),
+    }
+    result
+}
+
+/// A handle for registering cleanups in a [`try_scope`].
+pub struct TryScope<'env, T, E> {
+    stack: RefCell<Cleanups<'env, T, E>>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+type Cleanups<'env, T, E> = Vec<Box<dyn FnOnce(Outcome<T, E>) + 'env>>;
+
+enum Outcome<'r, T, E> {
+    Ok(&'r T),
+    Err(&'r E),
+    Panic,
+}
+
+impl<T, E> Clone for Outcome<'_, T, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, E> Copy for Outcome<'_, T, E> {}
+
+fn close<T, E>(stack: &mut Cleanups<T, E>, outcome: Outcome<T, E>) {
+    unwind(stack, Vec::pop, |_, f| f(outcome), |_, f| f(Outcome::Panic))
+}
+
+impl<'env, T, E> TryScope<'env, T, E> {
+    /// Registers a closure to be called when the scope ends, whatever the outcome.
+    pub fn defer(&self, f: impl FnOnce() + 'env) {
+        self.stack.borrow_mut().push(Box::new(move |_| f()))
+    }
+
+    /// Registers a closure to be called if the scope returns an error or panics.
+    ///
+    /// The closure is passed the error, or `None` if the scope panicked.
+    pub fn on_err(&self, f: impl FnOnce(Option<&E>) + 'env) {
+        self.stack
+            .borrow_mut()
+            .push(Box::new(move |outcome| match outcome {
+                Outcome::Ok(_) => {}
+                Outcome::Err(e) => f(Some(e)),
+                Outcome::Panic => f(None),
+            }))
+    }
+
+    /// Registers a closure to be called with the value if the scope succeeds.
+    pub fn on_ok(&self, f: impl FnOnce(&T) + 'env) {
+        self.stack.borrow_mut().push(Box::new(move |outcome| {
+            if let Outcome::Ok(value) = outcome {
+                f(value)
+            }
+        }))
+    }
+}
+
+impl<T, E> fmt::Debug for TryScope<'_, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TryScope")
+            .field("len", &self.stack.borrow().len())
+            .finish()
+    }
+}
+
+impl<T, E> Drop for TryScope<'_, T, E> {
+    fn drop(&mut self) {
+        // Only reached with pending closures if the scope panicked.
+        close(self.stack.get_mut(), Outcome::Panic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    /// Cleanups must be called, and deallocated once, when the scope unwinds.
+    fn runs_cleanups_on_unwind() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            scope(|s| {
+                for i in 0..3 {
+                    let (log, token) = (&log, check.token());
+                    s.defer(move || {
+                        log.borrow_mut().push(i);
+                        drop(token);
+                    });
+                }
+                panic!();
+            })
+        }));
+        assert!(r.is_err());
+        assert_eq!(*log.borrow(), [2, 1, 0]);
+        assert!(check.all_dropped());
+    }
+
+    #[test]
+    /// Each cleanup must only be called for its outcome, but always deallocated.
+    fn try_scope_runs_cleanups_for_outcome() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+
+        let run = |fail: Option<bool>| {
+            try_scope(|s| {
+                let (log, t1, t2, t3) = (&log, check.token(), check.token(), check.token());
+                s.defer(move || {
+                    log.borrow_mut().push(("defer", None));
+                    drop(t1);
+                });
+                s.on_ok(move |&v| {
+                    log.borrow_mut().push(("ok", Some(v)));
+                    drop(t2);
+                });
+                s.on_err(move |e| {
+                    log.borrow_mut().push(("err", e.copied()));
+                    drop(t3);
+                });
+                match fail {
+                    None => Ok(1),
+                    Some(false) => Err(2),
+                    Some(true) => panic!(),
+                }
+            })
+        };
+
+        assert_eq!(run(None), Ok(1));
+        assert_eq!(run(Some(false)), Err(2));
+        assert!(catch_unwind(AssertUnwindSafe(|| run(Some(true)))).is_err());
+        assert_eq!(
+            *log.borrow(),
+            [
+                ("ok", Some(1)),
+                ("defer", None),
+                ("err", Some(2)),
+                ("defer", None),
+                ("err", None),
+                ("defer", None),
+            ]
+        );
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,103 @@
+//! Where a guard was created.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::panic::Location;
+
+/// The creation site of a guard.
+///
+/// Zero-sized unless the `track-caller` feature is enabled. With `leak-detect` the guard is
+/// also registered as live until its site is dropped. All sites compare equal, so that guards'
+/// derived comparisons only depend on their closures.
+pub(crate) struct Site {
+    #[cfg(feature = "track-caller")]
+    location: &'static Location<'static>,
+    #[cfg(feature = "leak-detect")]
+    id: u64,
+    #[cfg(feature = "leak-detect")]
+    type_name: &'static str,
+}
+
+impl Site {
+    /// The site of the caller, if tracked, for a guard of type `G`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    #[cfg_attr(not(feature = "leak-detect"), allow(clippy::extra_unused_type_parameters))]
+    #[inline(always)]
+    pub(crate) fn caller<G: ?Sized>() -> Self {
+        #[cfg(feature = "track-caller")]
+        let location = Location::caller();
+        Self {
+            #[cfg(feature = "leak-detect")]
+            id: crate::leak::register(core::any::type_name::<G>(), location),
+            #[cfg(feature = "leak-detect")]
+            type_name: core::any::type_name::<G>(),
+            #[cfg(feature = "track-caller")]
+            location,
+        }
+    }
+
+    pub(crate) fn location(&self) -> Option<&'static Location<'static>> {
+        #[cfg(feature = "track-caller")]
+        {
+            Some(self.location)
+        }
+        #[cfg(not(feature = "track-caller"))]
+        {
+            None
+        }
+    }
+}
+
+impl Clone for Site {
+    fn clone(&self) -> Self {
+        Self {
+            #[cfg(feature = "track-caller")]
+            location: self.location,
+            #[cfg(feature = "leak-detect")]
+            id: crate::leak::register(self.type_name, self.location),
+            #[cfg(feature = "leak-detect")]
+            type_name: self.type_name,
+        }
+    }
+}
+
+#[cfg(feature = "leak-detect")]
+impl Drop for Site {
+    fn drop(&mut self) {
+        crate::leak::unregister(self.id)
+    }
+}
+
+impl fmt::Debug for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location() {
+            Some(location) => fmt::Display::fmt(location, f),
+            None => f.write_str("<untracked>"),
+        }
+    }
+}
+
+impl PartialEq for Site {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Site {}
+
+impl PartialOrd for Site {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Site {
+    fn cmp(&self, _: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl Hash for Site {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
@@ -0,0 +1,218 @@
+//! Undo logs with savepoints.
+
+use core::mem::{self, ManuallyDrop};
+
+use crate::{DropReason, ExitStack};
+
+/// An undo log that rolls back every step when dropped, unless committed.
+///
+/// Each step of a multi-part operation registers a closure that undoes it. If the operation
+/// fails part-way, whether by returning early or by panicking, dropping the transaction calls
+/// the undo closures in reverse order. Once every step has succeeded,
+/// [`commit`](Self::commit) discards them instead.
+///
+/// A [`savepoint`](Self::savepoint) marks a point that later steps can be rolled back to,
+/// without undoing the whole transaction.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::Transaction;
+/// use std::cell::RefCell;
+///
+/// let provisioned = &RefCell::new(Vec::new());
+/// let provision = |name| -> Result<(), String> {
+///     if name == "dns" {
+///         return Err(format!("{} failed", name));
+///     }
+///     provisioned.borrow_mut().push(name);
+///     Ok(())
+/// };
+///
+/// let r = (|| {
+///     let mut tx = Transaction::new();
+///     for name in ["vm", "disk", "dns"] {
+///         provision(name)?;
+///         tx.step(move || provisioned.borrow_mut().retain(|n| *n != name));
+///     }
+///     tx.commit();
+///     Ok::<_, String>(())
+/// })();
+/// assert_eq!(r, Err("dns failed".to_string()));
+/// assert!(provisioned.borrow().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    undo: ExitStack<'a>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Creates a new, empty, `Transaction`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new() -> Self {
+        Self {
+            undo: ExitStack::new(),
+        }
+    }
+
+    /// Records a step, with the closure that undoes it.
+    pub fn step(&mut self, undo: impl FnOnce() + 'a) {
+        self.undo.push(undo)
+    }
+
+    /// Marks a point that later steps can be rolled back to.
+    ///
+    /// Dropping the savepoint rolls back the steps recorded since it was created; releasing it
+    /// keeps them as part of the transaction.
+    ///
+    /// # Examples
+    /// ```
+    /// # use ondrop::Transaction;
+    /// use std::cell::Cell;
+    ///
+    /// let n = Cell::new(0);
+    /// let mut tx = Transaction::new();
+    /// n.set(1);
+    /// tx.step(|| n.set(0));
+    ///
+    /// let mut sp = tx.savepoint();
+    /// n.set(2);
+    /// sp.step(|| n.set(1));
+    /// sp.rollback();
+    /// assert_eq!(n.get(), 1);
+    ///
+    /// drop(tx);
+    /// assert_eq!(n.get(), 0);
+    /// ```
+    pub fn savepoint(&mut self) -> Savepoint<'_, 'a> {
+        Savepoint {
+            mark: self.undo.len(),
+            tx: self,
+        }
+    }
+
+    /// Commits the transaction, dropping the undo closures without calling them.
+    pub fn commit(self) {
+        self.undo.cancel()
+    }
+
+    /// Rolls the transaction back now, calling the undo closures in reverse order.
+    pub fn rollback(self) {
+        self.undo.close()
+    }
+
+    /// Returns the number of steps recorded.
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Returns `true` if no steps have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+}
+
+impl Default for Transaction<'_> {
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A point in a [`Transaction`] that later steps can be rolled back to.
+///
+/// Created by [`Transaction::savepoint`]. Steps are recorded through the savepoint while it is
+/// alive, and savepoints can be nested. Dropping it rolls back the steps recorded since it was
+/// created.
+#[derive(Debug)]
+pub struct Savepoint<'t, 'a> {
+    tx: &'t mut Transaction<'a>,
+    mark: usize,
+}
+
+impl<'a> Savepoint<'_, 'a> {
+    /// Records a step, with the closure that undoes it.
+    pub fn step(&mut self, undo: impl FnOnce() + 'a) {
+        self.tx.step(undo)
+    }
+
+    /// Marks a nested point that later steps can be rolled back to.
+    pub fn savepoint(&mut self) -> Savepoint<'_, 'a> {
+        self.tx.savepoint()
+    }
+
+    /// Keeps the steps recorded since the savepoint, as part of the enclosing transaction or
+    /// savepoint.
+    pub fn release(self) {
+        mem::forget(self)
+    }
+
+    /// Rolls back the steps recorded since the savepoint now, in reverse order.
+    pub fn rollback(self) {
+        let mut this = ManuallyDrop::new(self);
+        let mark = this.mark;
+        this.tx.undo.unwind_to(mark, DropReason::StackClose)
+    }
+
+    /// Returns the number of steps recorded since the savepoint.
+    pub fn len(&self) -> usize {
+        self.tx.len() - self.mark
+    }
+
+    /// Returns `true` if no steps have been recorded since the savepoint.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Savepoint<'_, '_> {
+    fn drop(&mut self) {
+        self.tx.undo.unwind_to(self.mark, DropReason::dropped())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::RefCell;
+
+    #[test]
+    /// Nested savepoints must only undo their own steps, and every undo closure must be
+    /// deallocated once whether or not it was called.
+    fn savepoints_roll_back_partially() {
+        let check = DropCheck::new();
+        let log = RefCell::new(Vec::new());
+        let undo = |i| {
+            let (log, token) = (&log, check.token());
+            move || {
+                log.borrow_mut().push(i);
+                drop(token);
+            }
+        };
+
+        let mut tx = Transaction::new();
+        tx.step(undo(0));
+        {
+            let mut outer = tx.savepoint();
+            outer.step(undo(1));
+            {
+                let mut inner = outer.savepoint();
+                inner.step(undo(2));
+                inner.step(undo(3));
+            }
+            assert_eq!(*log.borrow(), [3, 2]);
+            let mut inner = outer.savepoint();
+            inner.step(undo(4));
+            inner.release();
+            outer.release();
+        }
+        assert_eq!(tx.len(), 3);
+        tx.step(undo(5));
+        tx.commit();
+
+        assert_eq!(*log.borrow(), [3, 2]);
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,150 @@
+//! Guards for cleanups that can fail.
+
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::site::Site;
+
+/// Calls the wrapped fallible closure when dropped, passing any error to a handler.
+///
+/// Callers that want the error itself can call [`finish`](Self::finish) instead of dropping the
+/// guard; the handler is only for errors that would otherwise be lost.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::TryOnDrop;
+/// let mut errors = Vec::new();
+/// {
+///     let _guard = TryOnDrop::new(|| Err("fsync failed"), |e| errors.push(e));
+/// }
+/// assert_eq!(errors, ["fsync failed"]);
+/// ```
+///
+/// Getting the error explicitly:
+///
+/// ```
+/// # use ondrop::TryOnDrop;
+/// let guard = TryOnDrop::new(|| Err("fsync failed"), |_| panic!());
+/// assert_eq!(guard.finish(), Err("fsync failed"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TryOnDrop<F, H, E>
+where
+    F: FnOnce() -> Result<(), E>,
+    H: FnOnce(E),
+{
+    f: ManuallyDrop<F>,
+    on_error: ManuallyDrop<H>,
+    site: Site,
+    _marker: PhantomData<fn(E)>,
+}
+
+impl<F, H, E> TryOnDrop<F, H, E>
+where
+    F: FnOnce() -> Result<(), E>,
+    H: FnOnce(E),
+{
+    /// Creates a new `TryOnDrop` from a closure and an error handler.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(f: F, on_error: H) -> Self {
+        Self {
+            f: ManuallyDrop::new(f),
+            on_error: ManuallyDrop::new(on_error),
+            site: Site::caller::<Self>(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Calls the closure now, returning its result instead of passing errors to the handler.
+    ///
+    /// The handler is dropped without being called.
+    pub fn finish(self) -> Result<(), E> {
+        self.into_inner()()
+    }
+
+    /// Unwraps the closure without calling it.
+    ///
+    /// The handler is dropped without being called.
+    pub fn into_inner(self) -> F {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ManuallyDrop::drop(&mut this.on_error);
+            ptr::drop_in_place(&mut this.site);
+            ptr::read(&*this.f)
+        }
+    }
+}
+
+impl<F, H, E> Default for TryOnDrop<F, H, E>
+where
+    F: FnOnce() -> Result<(), E> + Default,
+    H: FnOnce(E) + Default,
+{
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn default() -> Self {
+        Self::new(F::default(), H::default())
+    }
+}
+
+impl<F, H, E> Drop for TryOnDrop<F, H, E>
+where
+    F: FnOnce() -> Result<(), E>,
+    H: FnOnce(E),
+{
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            let f: F = ptr::read(&*self.f);
+            let on_error: H = ptr::read(&*self.on_error);
+            if let Err(e) = f() {
+                on_error(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+    use std::cell::Cell;
+
+    #[test]
+    /// Make sure the closure and the handler are each deallocated once and only once.
+    fn drops_closure_and_handler_once() {
+        let check = DropCheck::new();
+        let handled = Cell::new(false);
+
+        let (f_token, h_token) = (check.token(), check.token());
+        let guard = TryOnDrop::new(
+            move || {
+                drop(f_token);
+                Err(())
+            },
+            |()| {
+                drop(h_token);
+                handled.set(true);
+            },
+        );
+        assert_eq!(guard.finish(), Err(()));
+        assert!(check.all_dropped());
+        assert!(!handled.get());
+
+        let (f_token, h_token) = (check.token(), check.token());
+        drop(TryOnDrop::new(
+            move || {
+                drop(f_token);
+                Err(())
+            },
+            |()| {
+                drop(h_token);
+                handled.set(true);
+            },
+        ));
+        assert!(check.all_dropped());
+        assert!(handled.get());
+    }
+}
@@ -0,0 +1,159 @@
+//! A `HashMap` that rolls back changes when dropped.
+
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+use core::ops::Deref;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+
+use crate::site::Site;
+
+enum MapOp<K, V> {
+    Insert(K, Option<V>),
+    Remove(K, V),
+}
+
+/// Borrows a `HashMap`, recording changes made through it, and reverts them when dropped
+/// unless committed.
+///
+/// The `HashMap` counterpart of [`TxVec`](crate::TxVec). Keys are cloned when inserted, so
+/// that the insertion can be undone.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::TxMap;
+/// use std::collections::HashMap;
+///
+/// let mut m = HashMap::from([("a", 1), ("b", 2)]);
+/// {
+///     let mut tx = TxMap::new(&mut m);
+///     assert_eq!(tx.insert("a", 10), Some(&1));
+///     assert_eq!(tx.insert("c", 3), None);
+///     assert_eq!(tx.remove("b"), Some(&2));
+///     assert_eq!(tx.len(), 2);
+/// }
+/// assert_eq!(m, HashMap::from([("a", 1), ("b", 2)]));
+/// ```
+pub struct TxMap<'a, K, V, S = RandomState>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    map: &'a mut HashMap<K, V, S>,
+    log: Vec<MapOp<K, V>>,
+    site: Site,
+}
+
+impl<'a, K, V, S> TxMap<'a, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Starts recording changes to a `HashMap`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(map: &'a mut HashMap<K, V, S>) -> Self {
+        Self {
+            map,
+            log: Vec::new(),
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Inserts a key-value pair, returning a reference to the value it replaced, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<&V> {
+        let old = self.map.insert(key.clone(), value);
+        self.log.push(MapOp::Insert(key, old));
+        match self.log.last() {
+            Some(MapOp::Insert(_, old)) => old.as_ref(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Removes a key, returning a reference to its value, if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.log.push(MapOp::Remove(key, value));
+        match self.log.last() {
+            Some(MapOp::Remove(_, value)) => Some(value),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Keeps the changes, dropping the values that were removed or replaced.
+    pub fn commit(mut self) {
+        self.log.clear()
+    }
+
+    /// Reverts the changes now.
+    pub fn rollback(self) {}
+}
+
+impl<K, V, S> Deref for TxMap<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    type Target = HashMap<K, V, S>;
+
+    fn deref(&self) -> &HashMap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V, S> fmt::Debug for TxMap<'_, K, V, S>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxMap")
+            .field("map", &self.map)
+            .field("changes", &self.log.len())
+            .field("site", &self.site)
+            .finish()
+    }
+}
+
+impl<K, V, S> Drop for TxMap<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        while let Some(op) = self.log.pop() {
+            match op {
+                MapOp::Insert(key, None) => drop(self.map.remove(&key)),
+                MapOp::Insert(key, Some(old)) => drop(self.map.insert(key, old)),
+                MapOp::Remove(key, value) => drop(self.map.insert(key, value)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Inserts, overwrites and removals must all be reverted.
+    fn reverts_changes() {
+        let mut m: HashMap<_, _> = (0..3).map(|i| (i, i)).collect();
+        let before = m.clone();
+
+        let mut tx = TxMap::new(&mut m);
+        tx.insert(3, 3);
+        tx.insert(0, 10);
+        tx.remove(&1);
+        tx.insert(1, 11);
+        tx.remove(&3);
+        drop(tx);
+        assert_eq!(m, before);
+    }
+}
@@ -0,0 +1,194 @@
+//! A `Vec` that rolls back changes when dropped.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+
+use crate::site::Site;
+
+enum VecOp<T> {
+    Push,
+    Pop(T),
+    Insert(usize),
+    Remove(usize, T),
+    Set(usize, T),
+}
+
+impl<T> VecOp<T> {
+    fn value(&self) -> &T {
+        match self {
+            VecOp::Pop(value) | VecOp::Remove(_, value) | VecOp::Set(_, value) => value,
+            VecOp::Push | VecOp::Insert(_) => unreachable!(),
+        }
+    }
+
+    fn undo(self, vec: &mut Vec<T>) {
+        match self {
+            VecOp::Push => drop(vec.pop()),
+            VecOp::Pop(value) => vec.push(value),
+            VecOp::Insert(index) => drop(vec.remove(index)),
+            VecOp::Remove(index, value) => vec.insert(index, value),
+            VecOp::Set(index, value) => vec[index] = value,
+        }
+    }
+}
+
+/// Borrows a `Vec`, recording changes made through it, and reverts them when dropped unless
+/// committed.
+///
+/// Values that are removed or overwritten are kept until the guard is dropped, so that they
+/// can be put back; the methods that remove them return references rather than the values
+/// themselves. The `Vec` can be read through `Deref`, but can only be changed through the
+/// guard's methods.
+///
+/// # Examples
+///
+/// ```
+/// # use ondrop::TxVec;
+/// let mut v = vec![1, 2, 3];
+/// {
+///     let mut tx = TxVec::new(&mut v);
+///     tx.push(4);
+///     assert_eq!(tx.remove(0), &1);
+///     tx.set(0, 20);
+///     assert_eq!(*tx, [20, 3, 4]);
+/// }
+/// assert_eq!(v, [1, 2, 3]);
+///
+/// let mut tx = TxVec::new(&mut v);
+/// tx.push(4);
+/// tx.commit();
+/// assert_eq!(v, [1, 2, 3, 4]);
+/// ```
+pub struct TxVec<'a, T> {
+    vec: &'a mut Vec<T>,
+    log: Vec<VecOp<T>>,
+    site: Site,
+}
+
+impl<'a, T> TxVec<'a, T> {
+    /// Starts recording changes to a `Vec`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn new(vec: &'a mut Vec<T>) -> Self {
+        Self {
+            vec,
+            log: Vec::new(),
+            site: Site::caller::<Self>(),
+        }
+    }
+
+    /// Appends a value to the back of the `Vec`.
+    pub fn push(&mut self, value: T) {
+        self.vec.push(value);
+        self.log.push(VecOp::Push);
+    }
+
+    /// Removes the last value from the `Vec`, returning a reference to it.
+    pub fn pop(&mut self) -> Option<&T> {
+        let value = self.vec.pop()?;
+        self.log.push(VecOp::Pop(value));
+        self.log.last().map(VecOp::value)
+    }
+
+    /// Inserts a value at `index`, shifting all values after it to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        self.vec.insert(index, value);
+        self.log.push(VecOp::Insert(index));
+    }
+
+    /// Removes the value at `index`, shifting all values after it to the left, and returns a
+    /// reference to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> &T {
+        let value = self.vec.remove(index);
+        self.log.push(VecOp::Remove(index, value));
+        self.log.last().unwrap().value()
+    }
+
+    /// Overwrites the value at `index`, returning a reference to the old value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set(&mut self, index: usize, value: T) -> &T {
+        let old = core::mem::replace(&mut self.vec[index], value);
+        self.log.push(VecOp::Set(index, old));
+        self.log.last().unwrap().value()
+    }
+
+    /// Keeps the changes, dropping the values that were removed or overwritten.
+    pub fn commit(mut self) {
+        self.log.clear()
+    }
+
+    /// Reverts the changes now.
+    pub fn rollback(self) {}
+}
+
+impl<T> Deref for TxVec<'_, T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        self.vec
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TxVec<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxVec")
+            .field("vec", &self.vec)
+            .field("changes", &self.log.len())
+            .field("site", &self.site)
+            .finish()
+    }
+}
+
+impl<T> Drop for TxVec<'_, T> {
+    fn drop(&mut self) {
+        while let Some(op) = self.log.pop() {
+            op.undo(self.vec);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dropcheck::DropCheck;
+
+    #[test]
+    /// Every change must be reverted, and every value deallocated once, whether or not the
+    /// changes are committed.
+    fn reverts_changes() {
+        let check = DropCheck::new();
+        let mut v: Vec<_> = (0..3).map(|i| (i, check.token())).collect();
+
+        let mut tx = TxVec::new(&mut v);
+        tx.push((3, check.token()));
+        tx.insert(1, (4, check.token()));
+        tx.remove(0);
+        tx.set(2, (5, check.token()));
+        tx.pop();
+        tx.pop();
+        assert_eq!(tx.iter().map(|t| t.0).collect::<Vec<_>>(), [4, 1]);
+        tx.rollback();
+        assert_eq!(v.iter().map(|t| t.0).collect::<Vec<_>>(), [0, 1, 2]);
+
+        let mut tx = TxVec::new(&mut v);
+        tx.remove(1);
+        tx.push((6, check.token()));
+        tx.commit();
+        assert_eq!(v.iter().map(|t| t.0).collect::<Vec<_>>(), [0, 2, 6]);
+
+        drop(v);
+        assert!(check.all_dropped());
+    }
+}
@@ -0,0 +1,37 @@
+//! The loop shared by every stack of cleanups.
+
+use core::mem;
+
+/// Pops cleanups off `state` with `pop` until it returns `None`, calling each with `call`.
+///
+/// If a cleanup panics, the remaining ones are still popped as the panic unwinds, and called with
+/// `on_panic` instead.
+pub(crate) fn unwind<S: ?Sized, T>(
+    state: &mut S,
+    pop: impl Fn(&mut S) -> Option<T> + Copy,
+    call: impl Fn(&mut S, T),
+    on_panic: impl Fn(&mut S, T) + Copy,
+) {
+    while let Some(f) = pop(state) {
+        let rest = Rest {
+            state: &mut *state,
+            unwind: |state: &mut S| unwind(state, pop, on_panic, on_panic),
+        };
+        call(rest.state, f);
+        mem::forget(rest);
+    }
+}
+
+/// Calls `unwind` on `state` when dropped.
+///
+/// A `DropWith` would do, but would be tracked as a guard under `leak-detect`.
+struct Rest<'s, S: ?Sized, F: FnMut(&mut S)> {
+    state: &'s mut S,
+    unwind: F,
+}
+
+impl<S: ?Sized, F: FnMut(&mut S)> Drop for Rest<'_, S, F> {
+    fn drop(&mut self) {
+        (self.unwind)(self.state)
+    }
+}